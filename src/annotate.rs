@@ -0,0 +1,99 @@
+//! Hangul with its romanization over it, like furigana.
+//!
+//! The text is split into words, each annotated with what it was typed
+//! as. In romanize mode it is the other way round: the typed Hangul is
+//! annotated with its romanization. Words without Hangul, and the spaces
+//! between words, have no annotation.
+
+use mapping::Mapping;
+use normalize;
+
+/// A word with the note to show over it, or the spaces between words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub base: String,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Annotation {
+    /// `<ruby>한국<rt>hangug</rt></ruby>`
+    Html,
+    /// `{한국|hangug}`, as the Markdown ruby extensions write it.
+    Markdown,
+}
+
+impl Annotation {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Annotation::Html => "html",
+            Annotation::Markdown => "markdown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Annotation> {
+        match name {
+            "html" | "ruby" => Some(Annotation::Html),
+            "markdown" | "md" => Some(Annotation::Markdown),
+            _ => None,
+        }
+    }
+
+    /// Writes the segments out in this format.
+    pub fn write(&self, segments: &[Segment]) -> String {
+        let mut written = String::new();
+        for segment in segments {
+            match (*self, segment.note.as_ref()) {
+                (Annotation::Html, Some(note)) => {
+                    written.push_str(&format!("<ruby>{}<rt>{}</rt></ruby>",
+                        escape_html(&segment.base), escape_html(note)));
+                }
+                (Annotation::Html, None) => written.push_str(&escape_html(&segment.base)),
+                (Annotation::Markdown, Some(note)) => {
+                    written.push_str(&format!("{{{}|{}}}", segment.base, note));
+                }
+                (Annotation::Markdown, None) => written.push_str(&segment.base),
+            }
+        }
+        written
+    }
+}
+
+/// Splits the converted text into annotated words. `source` is the text
+/// that was mapped, and `reversed` puts it in the base and the converted
+/// text in the notes, for romanize mode.
+pub fn segments(mapping: &Mapping, source: &str, reversed: bool) -> Vec<Segment> {
+    let chars = mapping.converted().chars().collect::<Vec<_>>();
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let space = chars[start].is_whitespace();
+        let end = chars[start..].iter()
+            .position(|ch| ch.is_whitespace() != space)
+            .map_or(chars.len(), |length| start + length);
+        let converted = chars[start..end].iter().cloned().collect::<String>();
+        if space {
+            segments.push(Segment { base: converted, note: None });
+            start = end;
+            continue;
+        }
+        let typed = source[mapping.source_offset(start)..mapping.source_offset(end)].trim();
+        let (base, note) = if reversed {
+            (typed.to_string(), converted)
+        } else {
+            (converted, typed.to_string())
+        };
+        let hangul = base.chars().any(|ch| normalize::split(ch).is_some());
+        let annotated = hangul && !note.is_empty() && note != base;
+        segments.push(Segment {
+            base: base,
+            note: if annotated { Some(note) } else { None },
+        });
+        start = end;
+    }
+    segments
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
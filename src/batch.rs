@@ -0,0 +1,597 @@
+//! Converting whole files, with `derowin convert` or by dropping them on
+//! the window with `drop.files = convert` set (rather than the default,
+//! `load`, which puts them in the buffer with everything but their prose
+//! between literal marks).
+//!
+//! The encoding of each file is guessed (UTF-8, UTF-16 or EUC-KR/CP949)
+//! and kept, and so are its line endings. Markdown, subtitles and CSV/TSV
+//! files only have their prose converted (see `format`); which CSV columns
+//! that means is up to `convert.columns` and `convert.header`, or the
+//! command line. Lines that still have Latin
+//! letters in them after conversion are reported, since that's usually a
+//! typo in the romanization.
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use encoding_rs::EUC_KR;
+
+use compose::Converter;
+use config::{self, Config};
+use error::DerowinError;
+use escape::Escapes;
+use format::{self, Column, Format, Scanner};
+
+/// What goes before the original extension on converted files, so that
+/// they keep their format.
+const CONVERTED_EXTENSION: &'static str = "ko";
+/// What marks the regions to convert with `--marked`, unless `--marks`
+/// says otherwise.
+const DEFAULT_MARKS: (&'static str, &'static str) = ("{{", "}}");
+
+pub const USAGE: &'static str = "\
+Usage: derowin convert [options] FILE...
+
+Converts each FILE to <name>.ko.<ext> next to it (notes.md to notes.ko.md),
+unless told otherwise.
+
+Options:
+    -i, --in-place      Replace each file with its conversion
+    -o, --output-dir DIR
+                        Write the conversions to DIR, under their own names
+    --marked            Only convert text between {{ and }}, dropping the
+                        marks
+    --marks OPEN CLOSE  Use OPEN and CLOSE as the marks (implies --marked)
+    --format FORMAT     Treat the files as plain, markdown, subtitles, csv
+                        or tsv, instead of going by their extensions
+    --columns LIST      Only convert these CSV/TSV columns, by number or
+                        header name, like 2,meaning
+    --header            Never convert the first CSV/TSV row";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// EUC-KR, or its CP949 superset, which has every Hangul syllable.
+    EucKr,
+}
+
+impl TextEncoding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf8Bom => "UTF-8 with BOM",
+            TextEncoding::Utf16Le => "UTF-16LE",
+            TextEncoding::Utf16Be => "UTF-16BE",
+            TextEncoding::EucKr => "EUC-KR",
+        }
+    }
+
+    /// Decodes a file, going by its byte order mark if it has one, and
+    /// otherwise trying each encoding in turn.
+    pub fn decode(bytes: &[u8]) -> Option<(String, TextEncoding)> {
+        if bytes.starts_with(b"\xEF\xBB\xBF") {
+            return String::from_utf8(bytes[3..].to_vec()).ok()
+                .map(|text| (text, TextEncoding::Utf8Bom));
+        }
+        if bytes.starts_with(b"\xFF\xFE") {
+            return decode_utf16(&bytes[2..], true).map(|text| (text, TextEncoding::Utf16Le));
+        }
+        if bytes.starts_with(b"\xFE\xFF") {
+            return decode_utf16(&bytes[2..], false).map(|text| (text, TextEncoding::Utf16Be));
+        }
+        // Romanized text is mostly ASCII, so UTF-16 without a mark is half
+        // zeros.
+        let zeros_at = |parity: usize| {
+            bytes.iter().skip(parity).step_by(2).filter(|&&byte| byte == 0).count()
+        };
+        if bytes.len() >= 2 && zeros_at(1) > bytes.len() / 4 {
+            return decode_utf16(bytes, true).map(|text| (text, TextEncoding::Utf16Le));
+        }
+        if bytes.len() >= 2 && zeros_at(0) > bytes.len() / 4 {
+            return decode_utf16(bytes, false).map(|text| (text, TextEncoding::Utf16Be));
+        }
+        if let Ok(text) = String::from_utf8(bytes.to_vec()) {
+            return Some((text, TextEncoding::Utf8));
+        }
+        EUC_KR.decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|text| (text.into_owned(), TextEncoding::EucKr))
+    }
+
+    /// Encodes text, or gives up if the encoding can't hold all of it.
+    /// UTF-16 always gets a byte order mark.
+    pub fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        match *self {
+            TextEncoding::Utf8 => Some(text.as_bytes().to_vec()),
+            TextEncoding::Utf8Bom => {
+                let mut bytes = b"\xEF\xBB\xBF".to_vec();
+                bytes.extend_from_slice(text.as_bytes());
+                Some(bytes)
+            }
+            TextEncoding::Utf16Le => Some(encode_utf16(text, true)),
+            TextEncoding::Utf16Be => Some(encode_utf16(text, false)),
+            TextEncoding::EucKr => {
+                let (bytes, _, unmappable) = EUC_KR.encode(text);
+                if unmappable { None } else { Some(bytes.into_owned()) }
+            }
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], little_endian: bool) -> Option<String> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let units = bytes.chunks(2)
+        .map(|pair| if little_endian {
+            u16::from_le_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_be_bytes([pair[0], pair[1]])
+        })
+        .collect::<Vec<_>>();
+    String::from_utf16(&units).ok()
+}
+
+fn encode_utf16(text: &str, little_endian: bool) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(2 + text.len() * 2);
+    for unit in Some(0xFEFF).into_iter().chain(text.encode_utf16()) {
+        let pair: [u8; 2] = if little_endian { unit.to_le_bytes() } else { unit.to_be_bytes() };
+        bytes.extend_from_slice(&pair);
+    }
+    bytes
+}
+
+/// Where converted files go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// `<name>.ko.<ext>` next to the original, keeping its extension.
+    Beside,
+    InPlace,
+    /// A directory, under the original name.
+    Directory(PathBuf),
+}
+
+/// How to convert files.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub destination: Destination,
+    /// If set, only the text between these marks is converted.
+    pub marks: Option<(String, String)>,
+    /// The format of every file, instead of going by their extensions.
+    pub format: Option<Format>,
+    /// The CSV and TSV columns to convert, or all of them if empty.
+    pub columns: Vec<Column>,
+    pub header: bool,
+}
+
+/// What came of converting a file.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub target: PathBuf,
+    pub format: Format,
+    pub encoding: TextEncoding,
+    /// The lines, numbered from 1, that still had Latin letters after
+    /// conversion, as converted.
+    pub unconverted: Vec<(usize, String)>,
+}
+
+impl Batch {
+    pub fn from_config(config: &Config) -> Result<Batch, DerowinError> {
+        Ok(Batch {
+            destination: Destination::Beside,
+            marks: None,
+            format: None,
+            columns: config.parse_with("convert.columns", format::parse_columns)?
+                .unwrap_or_default(),
+            header: config.parse_with("convert.header", config::parse_bool)?.unwrap_or(false),
+        })
+    }
+
+    /// Where the conversion of a file goes.
+    pub fn target(&self, path: &Path) -> PathBuf {
+        match self.destination {
+            Destination::Beside => {
+                let mut name = path.file_stem().unwrap_or(path.as_os_str()).to_os_string();
+                name.push(".");
+                name.push(CONVERTED_EXTENSION);
+                if let Some(extension) = path.extension() {
+                    name.push(".");
+                    name.push(extension);
+                }
+                path.with_file_name(name)
+            }
+            Destination::InPlace => path.to_path_buf(),
+            Destination::Directory(ref dir) => dir.join(path.file_name().unwrap_or(path.as_os_str())),
+        }
+    }
+
+    /// Converts text line by line, keeping its line endings.
+    pub fn convert_text(&self, converter: &Converter, text: &str, format: Format) -> (String, Vec<(usize, String)>) {
+        let mut converted = String::with_capacity(text.len() * 2);
+        let mut unconverted = Vec::new();
+        let mut scanner = Scanner::new(format, &self.columns, self.header);
+        // Marked regions can go over several lines.
+        let mut inside = false;
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let content = line.trim_end_matches(|ch| ch == '\n' || ch == '\r');
+            let mut latin_left = false;
+            let line_converted = scanner.line(content, |prose| {
+                let (prose_converted, latin) = match self.marks {
+                    Some((ref open, ref close)) => {
+                        convert_marked(converter, prose, open, close, &mut inside)
+                    }
+                    None => convert_checked(converter, prose),
+                };
+                latin_left = latin_left || latin;
+                prose_converted
+            });
+            if latin_left {
+                unconverted.push((i + 1, line_converted.clone()));
+            }
+            converted.push_str(&line_converted);
+            converted.push_str(&line[content.len()..]);
+        }
+        (converted, unconverted)
+    }
+
+    /// Reads a file of romanized text to edit, in whichever encoding it is
+    /// in. What its format doesn't convert goes between literal marks, so
+    /// that it stays as it is.
+    pub fn load(&self, escapes: &Escapes, path: &Path) -> Result<String, DerowinError> {
+        let read_err = |e: io::Error| DerowinError::File(path.display().to_string(), e);
+        let bytes = fs::read(path).map_err(&read_err)?;
+        let (text, _) = TextEncoding::decode(&bytes)
+            .ok_or_else(|| read_err(invalid_data("not UTF-8, UTF-16 or EUC-KR text")))?;
+        let format = self.format.unwrap_or_else(|| Format::from_path(path));
+        if format == Format::Plain {
+            return Ok(text);
+        }
+        let (open, close) = escapes.mark_pairs()[0];
+        let mut loaded = String::with_capacity(text.len() * 2);
+        let keep = |text: &str, loaded: &mut String| {
+            if !text.is_empty() {
+                loaded.push_str(open);
+                loaded.push_str(text);
+                loaded.push_str(close);
+            }
+        };
+        let mut scanner = Scanner::new(format, &self.columns, self.header);
+        for line in text.split_inclusive('\n') {
+            let content = line.trim_end_matches(|ch| ch == '\n' || ch == '\r');
+            let mut kept = 0;
+            for (start, end) in scanner.prose(content) {
+                keep(&content[kept..start], &mut loaded);
+                loaded.push_str(&content[start..end]);
+                kept = end;
+            }
+            keep(&content[kept..], &mut loaded);
+            loaded.push_str(&line[content.len()..]);
+        }
+        Ok(loaded)
+    }
+
+    pub fn convert_file(&self, converter: &Converter, path: &Path) -> Result<Report, DerowinError> {
+        let read_err = |e: io::Error| DerowinError::File(path.display().to_string(), e);
+        let bytes = fs::read(path).map_err(&read_err)?;
+        let (text, encoding) = TextEncoding::decode(&bytes)
+            .ok_or_else(|| read_err(invalid_data("not UTF-8, UTF-16 or EUC-KR text")))?;
+        let format = self.format.unwrap_or_else(|| Format::from_path(path));
+        let (converted, unconverted) = self.convert_text(converter, &text, format);
+        let target = self.target(path);
+        let write_err = |e: io::Error| DerowinError::File(target.display().to_string(), e);
+        let unencodable = format!("can't be written as {}", encoding.name());
+        let bytes = encoding.encode(&converted)
+            .ok_or_else(|| write_err(invalid_data(&unencodable)))?;
+        File::create(&target)
+            .and_then(|mut file| file.write_all(&bytes))
+            .map_err(&write_err)?;
+        info!("Converted {} to {} ({}, {})", path.display(), target.display(), format.name(),
+            encoding.name());
+        Ok(Report {
+            target: target,
+            format: format,
+            encoding: encoding,
+            unconverted: unconverted,
+        })
+    }
+}
+
+/// Converts what's between the marks in a line, dropping the marks.
+/// `inside` says whether the line starts in a marked region, and is left
+/// saying whether it ends in one. Also says whether Latin letters were left
+/// in the marked text.
+fn convert_marked(converter: &Converter, line: &str, open: &str, close: &str, inside: &mut bool)
+    -> (String, bool)
+{
+    let mut converted = String::with_capacity(line.len() * 2);
+    let mut latin_left = false;
+    let mut rest = line;
+    loop {
+        let mark = if *inside { close } else { open };
+        let (region, next) = match rest.find(mark) {
+            Some(i) => (&rest[..i], Some(&rest[i + mark.len()..])),
+            None => (rest, None),
+        };
+        if *inside {
+            let (region_converted, latin) = convert_checked(converter, region);
+            latin_left = latin_left || latin;
+            converted.push_str(&region_converted);
+        } else {
+            converted.push_str(region);
+        }
+        match next {
+            Some(next) => {
+                rest = next;
+                *inside = !*inside;
+            }
+            None => return (converted, latin_left),
+        }
+    }
+}
+
+/// Converts text, also saying whether Latin letters were left in what
+/// went to dero. Letters kept literal, in marks or in URLs, e-mail
+/// addresses and numbers, were meant to stay.
+fn convert_checked(converter: &Converter, text: &str) -> (String, bool) {
+    let (converted, literal) = converter.convert_marked(text);
+    let kept = |i: usize| literal.iter().any(|&(start, end)| start <= i && i < end);
+    let latin = converted.chars().enumerate()
+        .any(|(i, ch)| ch.is_ascii_alphabetic() && !kept(i));
+    (converted, latin)
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+
+/// Runs `derowin convert`, given the arguments after `convert`.
+pub fn run(config: &Config, converter: &Converter, args: &[String]) -> Result<(), DerowinError> {
+    let mut batch = Batch::from_config(config)?;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--in-place" => batch.destination = Destination::InPlace,
+            "-o" | "--output-dir" => {
+                let dir = args.next().ok_or_else(|| missing_value(arg))?;
+                batch.destination = Destination::Directory(PathBuf::from(dir));
+            }
+            "--marked" => if batch.marks.is_none() {
+                batch.marks = Some((DEFAULT_MARKS.0.to_string(), DEFAULT_MARKS.1.to_string()));
+            },
+            "--marks" => {
+                let open = args.next().ok_or_else(|| missing_value(arg))?;
+                let close = args.next().ok_or_else(|| missing_value(arg))?;
+                if open.is_empty() || close.is_empty() {
+                    return Err(DerowinError::Usage(String::from("The marks can't be empty")));
+                }
+                batch.marks = Some((open.clone(), close.clone()));
+            }
+            "--format" => {
+                let name = args.next().ok_or_else(|| missing_value(arg))?;
+                let format = Format::from_name(name)
+                    .ok_or_else(|| DerowinError::Usage(format!("Unknown format '{}'", name)))?;
+                batch.format = Some(format);
+            }
+            "--columns" => {
+                let list = args.next().ok_or_else(|| missing_value(arg))?;
+                batch.columns = format::parse_columns(list)
+                    .ok_or_else(|| DerowinError::Usage(format!("Bad column list '{}'", list)))?;
+            }
+            "--header" => batch.header = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => {
+                return Err(DerowinError::Usage(format!("Unknown convert option '{}'", arg)));
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        return Err(DerowinError::Usage(String::from("No files to convert")));
+    }
+    if let Destination::Directory(ref dir) = batch.destination {
+        fs::create_dir_all(dir).map_err(|e| DerowinError::File(dir.display().to_string(), e))?;
+    }
+
+    let mut failures = Vec::new();
+    let mut converted = 0;
+    let mut unconverted_lines = 0;
+    for path in &paths {
+        match batch.convert_file(converter, path) {
+            Ok(report) => {
+                println!("{} -> {} ({}, {})", path.display(), report.target.display(),
+                    report.format.name(), report.encoding.name());
+                for &(line, ref text) in &report.unconverted {
+                    println!("    {}:{}: {}", report.target.display(), line, text);
+                }
+                converted += 1;
+                unconverted_lines += report.unconverted.len();
+            }
+            Err(err) => failures.push(err),
+        }
+    }
+    println!("Converted {} of {} files; {} lines left with unconverted text",
+        converted, paths.len(), unconverted_lines);
+    // The last failure is reported by the caller, and decides the exit code.
+    let last = failures.pop();
+    for err in failures {
+        eprintln!("derowin: {}", err);
+    }
+    match last {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn missing_value(option: &str) -> DerowinError {
+    DerowinError::Usage(format!("Missing value for '{}'", option))
+}
+
+/// Dropped files waiting to be converted. They are done one at a time, so
+/// that the window can show how far it has got.
+#[derive(Debug, Clone)]
+pub struct Queue {
+    batch: Batch,
+    pending: VecDeque<PathBuf>,
+    done: usize,
+    failed: usize,
+}
+
+impl Queue {
+    pub fn new(batch: Batch) -> Queue {
+        Queue {
+            batch: batch,
+            pending: VecDeque::new(),
+            done: 0,
+            failed: 0,
+        }
+    }
+
+    pub fn push(&mut self, path: PathBuf) {
+        // A new batch starts once the last one is finished.
+        if self.pending.is_empty() {
+            self.done = 0;
+            self.failed = 0;
+        }
+        self.pending.push_back(path);
+    }
+
+    /// Converts the next file, if there is one.
+    pub fn convert_next(&mut self, converter: &Converter) -> Option<Result<Report, DerowinError>> {
+        let path = self.pending.pop_front()?;
+        let result = self.batch.convert_file(converter, &path);
+        match result {
+            Ok(_) => self.done += 1,
+            Err(_) => self.failed += 1,
+        }
+        Some(result)
+    }
+
+    /// How far the batch has got, for the status bar.
+    pub fn progress(&self) -> String {
+        let finished = self.done + self.failed;
+        let total = finished + self.pending.len();
+        if !self.pending.is_empty() {
+            format!("converting {} of {} files", finished + 1, total)
+        } else if self.failed > 0 {
+            format!("saved {} of {} files, {} failed", self.done, total, self.failed)
+        } else if self.done == 1 {
+            String::from("saved 1 file")
+        } else {
+            format!("saved {} files", self.done)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::{env, fs, process};
+
+    use compose::Converter;
+    use escape::Escapes;
+    use format::Format;
+
+    use super::{Batch, Destination, TextEncoding};
+
+    fn batch(marks: Option<(&str, &str)>) -> Batch {
+        Batch {
+            destination: Destination::Beside,
+            marks: marks.map(|(open, close)| (open.to_string(), close.to_string())),
+            format: None,
+            columns: Vec::new(),
+            header: false,
+        }
+    }
+
+    #[test]
+    fn keeps_the_extension_beside_the_original() {
+        let target = |path: &str| batch(None).target(Path::new(path));
+        assert_eq!(target("a/notes.md"), PathBuf::from("a/notes.ko.md"));
+        assert_eq!(target("movie.en.srt"), PathBuf::from("movie.en.ko.srt"));
+        assert_eq!(target("README"), PathBuf::from("README.ko"));
+    }
+
+    #[test]
+    fn loads_syntax_as_literal_text() {
+        let path = env::temp_dir().join(format!("derowin-load-test-{}.srt", process::id()));
+        fs::write(&path, "1\r\n00:00:01,000 --> 00:00:02,000\r\n<i>hangug</i> eo\r\n").unwrap();
+        let loaded = batch(None).load(&Escapes::new(), &path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(),
+            "⟦1⟧\r\n⟦00:00:01,000 --> 00:00:02,000⟧\r\n⟦<i>⟧hangug⟦</i>⟧ eo\r\n");
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let text = "hangug 한국\r\n";
+        let encodings = [TextEncoding::Utf8, TextEncoding::Utf8Bom, TextEncoding::Utf16Le,
+            TextEncoding::Utf16Be];
+        for &encoding in &encodings {
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(TextEncoding::decode(&bytes), Some((text.to_string(), encoding)));
+        }
+    }
+
+    #[test]
+    fn converts_euc_kr_and_writes_it_back() {
+        let converter = Converter::new();
+        let bytes = TextEncoding::EucKr.encode("hangug 한국\r\n").unwrap();
+        assert!(bytes.ends_with(b" \xC7\xD1\xB1\xB9\r\n"));
+        let (text, encoding) = TextEncoding::decode(&bytes).unwrap();
+        assert_eq!(encoding, TextEncoding::EucKr);
+        let (converted, _) = batch(None).convert_text(&converter, &text, Format::Plain);
+        let written = encoding.encode(&converted).unwrap();
+        assert_eq!(TextEncoding::decode(&written), Some((converted, TextEncoding::EucKr)));
+    }
+
+    #[test]
+    fn finds_utf16_without_a_byte_order_mark() {
+        let little = "hangug".encode_utf16().flat_map(|unit| unit.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(TextEncoding::decode(&little),
+            Some(("hangug".to_string(), TextEncoding::Utf16Le)));
+        let big = "hangug".encode_utf16().flat_map(|unit| unit.to_be_bytes().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(TextEncoding::decode(&big),
+            Some(("hangug".to_string(), TextEncoding::Utf16Be)));
+        assert_eq!(TextEncoding::decode(b"\xFF\xFEa"), None);
+    }
+
+    #[test]
+    fn keeps_line_endings() {
+        let converter = Converter::new();
+        let text = "hangug\r\neo\n\nmal";
+        let (converted, _) = batch(None).convert_text(&converter, text, Format::Plain);
+        assert_eq!(converted, format!("{}\r\n{}\n\n{}", converter.convert("hangug"),
+            converter.convert("eo"), converter.convert("mal")));
+    }
+
+    #[test]
+    fn only_reports_letters_that_were_meant_to_convert() {
+        let mut converter = Converter::new();
+        converter.escapes = Escapes { urls: true, numbers: true, emails: true, ..Escapes::new() };
+        let text = "⟦abc⟧ https://example.com/x a@example.com 3.5\nqq";
+        let (_, unconverted) = batch(None).convert_text(&converter, text, Format::Plain);
+        assert_eq!(unconverted.iter().map(|&(line, _)| line).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn converts_marked_text_over_lines() {
+        let converter = Converter::new();
+        let text = "keep {{han\ngug}} keep";
+        let (converted, _) = batch(Some(("{{", "}}")))
+            .convert_text(&converter, text, Format::Plain);
+        assert_eq!(converted, format!("keep {}\n{} keep", converter.convert("han"),
+            converter.convert("gug")));
+    }
+}
@@ -0,0 +1,465 @@
+//! An IBus engine, so that romanization can be typed as Hangul in any
+//! application. The preedit shows the converted text, and it is committed
+//! with the same keys as in input mode.
+//!
+//! ibus-daemon starts the engine with `--ibus` when it is picked, going by
+//! the component file in `data/derowin-ibus.xml` (which goes in
+//! `/usr/share/ibus/component`). Fcitx5 can't load IBus engines, so it
+//! isn't supported yet.
+//!
+//! The engine reads the same config file and rules as the window, so it
+//! keeps the same text literal, and picks up changes to the rules as it
+//! runs.
+
+#[cfg(target_os = "linux")]
+extern crate dbus;
+extern crate derowin;
+#[cfg(target_os = "linux")]
+#[macro_use]
+extern crate log;
+
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::env;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::process;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
+
+#[cfg(target_os = "linux")]
+use dbus::arg::{RefArg, Variant};
+#[cfg(target_os = "linux")]
+use dbus::blocking::Connection;
+#[cfg(target_os = "linux")]
+use dbus::channel::Channel;
+#[cfg(target_os = "linux")]
+use dbus::message::MessageType;
+#[cfg(target_os = "linux")]
+use dbus::{Message, Path};
+
+#[cfg(target_os = "linux")]
+use derowin::compose::{self, Action, Converter, Effect};
+#[cfg(target_os = "linux")]
+use derowin::config::Config;
+#[cfg(target_os = "linux")]
+use derowin::editor::Editor;
+use derowin::error::DerowinError;
+#[cfg(target_os = "linux")]
+use derowin::escape::Escapes;
+#[cfg(target_os = "linux")]
+use derowin::logging::{self, redact};
+#[cfg(target_os = "linux")]
+use derowin::rules::{self, RuleFile};
+#[cfg(target_os = "linux")]
+use derowin::DeroMode;
+
+const USAGE: &'static str = "\
+Usage: derowin-ibus [options]
+
+Options:
+    --ibus              Started by ibus-daemon (the default anyway)
+    -v, --verbose       Log more (repeat for debug and trace output)
+    -h, --help          Show this message";
+
+#[cfg(target_os = "linux")]
+const BUS_NAME: &'static str = "org.freedesktop.IBus.Derowin";
+#[cfg(target_os = "linux")]
+const ENGINE_NAME: &'static str = "derowin";
+#[cfg(target_os = "linux")]
+const FACTORY_PATH: &'static str = "/org/freedesktop/IBus/Factory";
+#[cfg(target_os = "linux")]
+const ENGINE_PATH: &'static str = "/org/freedesktop/IBus/Engine/Derowin";
+#[cfg(target_os = "linux")]
+const ENGINE_INTERFACE: &'static str = "org.freedesktop.IBus.Engine";
+
+/// Modifier bits in key event states.
+#[cfg(target_os = "linux")]
+mod modifier {
+    pub const SHIFT: u32 = 1 << 0;
+    pub const CONTROL: u32 = 1 << 2;
+    pub const ALT: u32 = 1 << 3;
+    pub const SUPER: u32 = 1 << 26;
+    pub const RELEASE: u32 = 1 << 30;
+}
+
+/// The X keysyms of the keys that edit the preedit.
+#[cfg(target_os = "linux")]
+mod keyval {
+    pub const RETURN: u32 = 0xff0d;
+    pub const KP_ENTER: u32 = 0xff8d;
+    pub const BACKSPACE: u32 = 0xff08;
+    pub const DELETE: u32 = 0xffff;
+    pub const LEFT: u32 = 0xff51;
+    pub const RIGHT: u32 = 0xff53;
+    pub const HOME: u32 = 0xff50;
+    pub const END: u32 = 0xff57;
+    pub const Z: u32 = 0x5a;
+    pub const LOWER_Z: u32 = 0x7a;
+    /// Shift, Control and the other modifiers themselves.
+    pub const FIRST_MODIFIER: u32 = 0xffe1;
+    pub const LAST_MODIFIER: u32 = 0xffee;
+}
+
+/// Left in the preedit when the focus moves, the text is committed.
+#[cfg(target_os = "linux")]
+const PREEDIT_COMMIT: u32 = 1;
+#[cfg(target_os = "linux")]
+const ATTR_UNDERLINE: u32 = 1;
+#[cfg(target_os = "linux")]
+const UNDERLINE_SINGLE: u32 = 1;
+
+/// The `a{sv}` attachments that every serialized IBus object starts with.
+#[cfg(target_os = "linux")]
+type Attachments = HashMap<&'static str, Variant<Box<dyn RefArg>>>;
+#[cfg(target_os = "linux")]
+type IbusAttribute = (&'static str, Attachments, u32, u32, u32, u32);
+#[cfg(target_os = "linux")]
+type IbusAttrList = (&'static str, Attachments, Vec<Variant<IbusAttribute>>);
+#[cfg(target_os = "linux")]
+type IbusText = (&'static str, Attachments, String, Variant<IbusAttrList>);
+
+pub fn main() {
+    if let Err(err) = run() {
+        eprintln!("derowin-ibus: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+/// One input context's engine.
+#[cfg(target_os = "linux")]
+struct Engine {
+    path: String,
+    editor: Editor,
+}
+
+#[cfg(target_os = "linux")]
+impl Engine {
+    fn new(path: String) -> Engine {
+        Engine {
+            path: path,
+            editor: Editor::new(),
+        }
+    }
+
+    /// Handles a key, returning whether it was used.
+    fn process_key(&mut self, channel: &Channel, converter: &Converter, keyval: u32, state: u32)
+        -> bool
+    {
+        if state & modifier::RELEASE != 0 {
+            return false;
+        }
+        let composing = !self.editor.is_empty();
+        let action = match action_for(keyval, state, composing) {
+            Some(action) => action,
+            None => {
+                // Other keys act on the application, so the text goes first.
+                let is_modifier = keyval >= keyval::FIRST_MODIFIER
+                    && keyval <= keyval::LAST_MODIFIER;
+                if composing && !is_modifier {
+                    self.commit(channel, converter);
+                }
+                return false;
+            }
+        };
+        match compose::apply(&mut self.editor, DeroMode::Input, action) {
+            Effect::Ignored => false,
+            Effect::Edited => {
+                self.update_preedit(channel, converter);
+                true
+            }
+            Effect::Commit => {
+                self.commit(channel, converter);
+                true
+            }
+        }
+    }
+
+    fn commit(&mut self, channel: &Channel, converter: &Converter) {
+        if self.editor.is_empty() {
+            return;
+        }
+        let converted = converter.convert(&self.editor.take());
+        info!("Committed {}", redact(&converted));
+        self.update_preedit(channel, converter);
+        self.emit(channel, "CommitText", |signal| signal.append1(ibus_text(&converted, false)));
+    }
+
+    fn update_preedit(&self, channel: &Channel, converter: &Converter) {
+        let (preedit, caret) = converter.preedit(&self.editor);
+        let visible = !preedit.is_empty();
+        self.emit(channel, "UpdatePreeditText", |signal| {
+            signal.append3(ibus_text(&preedit, true), caret as u32, visible)
+                .append1(PREEDIT_COMMIT)
+        });
+    }
+
+    fn emit<F>(&self, channel: &Channel, name: &'static str, args: F)
+        where F: FnOnce(Message) -> Message
+    {
+        let signal = Message::signal(&Path::from(self.path.clone()), &ENGINE_INTERFACE.into(),
+            &name.into());
+        if channel.send(args(signal)).is_err() {
+            warn!("Could not send {} to IBus", name);
+        }
+    }
+}
+
+/// What a key does to the preedit, if anything. Only letters start a new
+/// word, so that other keys reach the application as usual.
+#[cfg(target_os = "linux")]
+fn action_for(keyval: u32, state: u32, composing: bool) -> Option<Action> {
+    let shift = state & modifier::SHIFT != 0;
+    let control = state & modifier::CONTROL != 0;
+    if state & (modifier::ALT | modifier::SUPER) != 0 {
+        return None;
+    }
+    let action = match keyval {
+        keyval::RETURN | keyval::KP_ENTER if control => return None,
+        keyval::RETURN | keyval::KP_ENTER if shift => Action::Newline,
+        keyval::RETURN | keyval::KP_ENTER => Action::Enter,
+        keyval::Z | keyval::LOWER_Z if control && shift => Action::Redo,
+        keyval::Z | keyval::LOWER_Z if control => Action::Undo,
+        _ if control => return None,
+        keyval::BACKSPACE => Action::Backspace,
+        keyval::DELETE => Action::Delete,
+        keyval::LEFT => Action::Left,
+        keyval::RIGHT => Action::Right,
+        keyval::HOME => Action::Home,
+        keyval::END => Action::End,
+        0x20..=0x7e => {
+            let ch = keyval as u8 as char;
+            if !composing && !ch.is_ascii_alphabetic() {
+                return None;
+            }
+            Action::Insert(ch.to_string())
+        }
+        _ => return None,
+    };
+    // Editing keys only belong to the engine while there is a preedit.
+    match action {
+        Action::Insert(_) => Some(action),
+        _ if composing => Some(action),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn ibus_text(text: &str, underline: bool) -> Variant<IbusText> {
+    let mut attributes = Vec::new();
+    if underline {
+        let end = text.chars().count() as u32;
+        attributes.push(Variant(("IBusAttribute", Attachments::new(), ATTR_UNDERLINE,
+            UNDERLINE_SINGLE, 0, end)));
+    }
+    let attr_list = ("IBusAttrList", Attachments::new(), attributes);
+    Variant(("IBusText", Attachments::new(), text.to_string(), Variant(attr_list)))
+}
+
+/// Serves the engine factory and the engines it makes.
+#[cfg(target_os = "linux")]
+struct Server {
+    engines: HashMap<String, Engine>,
+    created: usize,
+    converter: Converter,
+    rule_file: Option<RuleFile>,
+}
+
+#[cfg(target_os = "linux")]
+impl Server {
+    /// Reads the rules again if their file changed. Bad rules are logged,
+    /// and the last good ones kept.
+    fn reload_rules(&mut self) {
+        let reloaded = match self.rule_file {
+            Some(ref mut rule_file) => rule_file.reload(),
+            None => None,
+        };
+        match reloaded {
+            Some(Ok(rules)) => {
+                info!("Rules loaded");
+                self.converter.rules = rules;
+            }
+            Some(Err(err)) => warn!("{}", err),
+            None => {}
+        }
+    }
+
+    fn handle(&mut self, channel: &Channel, call: Message) {
+        if call.msg_type() != MessageType::MethodCall {
+            return;
+        }
+        let path = call.path().map(|path| path.to_string()).unwrap_or_default();
+        let interface = call.interface().map(|name| name.to_string()).unwrap_or_default();
+        let member = call.member().map(|name| name.to_string()).unwrap_or_default();
+        trace!("{} {}.{}", path, interface, member);
+        let reply = if path == FACTORY_PATH && member == "CreateEngine" {
+            match call.read1::<&str>() {
+                Ok(ENGINE_NAME) => {
+                    self.created += 1;
+                    let engine_path = format!("{}/{}", ENGINE_PATH, self.created);
+                    debug!("Created engine {}", engine_path);
+                    self.engines.insert(engine_path.clone(), Engine::new(engine_path.clone()));
+                    call.method_return().append1(Path::from(engine_path))
+                }
+                _ => error_reply(&call, "org.freedesktop.DBus.Error.InvalidArgs", "Unknown engine"),
+            }
+        } else if interface == "org.freedesktop.DBus.Properties" {
+            error_reply(&call, "org.freedesktop.DBus.Error.UnknownProperty", "No properties")
+        } else if member == "Destroy" && self.engines.remove(&path).is_some() {
+            debug!("Destroyed engine {}", path);
+            call.method_return()
+        } else if let Some(engine) = self.engines.get_mut(&path) {
+            match member.as_str() {
+                "ProcessKeyEvent" => match call.read3::<u32, u32, u32>() {
+                    Ok((keyval, _keycode, state)) => {
+                        let used = engine.process_key(channel, &self.converter, keyval, state);
+                        call.method_return().append1(used)
+                    }
+                    Err(_) => error_reply(&call, "org.freedesktop.DBus.Error.InvalidArgs",
+                        "Expected a key event"),
+                },
+                "FocusOut" | "Reset" | "Disable" => {
+                    engine.commit(channel, &self.converter);
+                    call.method_return()
+                }
+                // The rest (like SetCursorLocation) don't matter to the
+                // engine, and don't return anything.
+                _ => call.method_return(),
+            }
+        } else {
+            error_reply(&call, "org.freedesktop.DBus.Error.UnknownObject", "No such object")
+        };
+        if channel.send(reply).is_err() {
+            warn!("Could not reply to {}", member);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn error_reply(call: &Message, name: &'static str, message: &str) -> Message {
+    let message = CString::new(message).unwrap_or_default();
+    call.error(&name.into(), &message)
+}
+
+/// Finds the IBus daemon's private bus.
+#[cfg(target_os = "linux")]
+fn ibus_address() -> Result<String, DerowinError> {
+    if let Some(address) = env::var("IBUS_ADDRESS").ok().filter(|a| !a.is_empty()) {
+        return Ok(address);
+    }
+    let output = process::Command::new("ibus").arg("address").output()
+        .map_err(|e| DerowinError::Ibus(format!("could not run 'ibus address': {}", e)))?;
+    let address = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || address.is_empty() || address == "(null)" {
+        return Err(DerowinError::Ibus(String::from("ibus-daemon is not running")));
+    }
+    Ok(address)
+}
+
+#[cfg(target_os = "linux")]
+fn run() -> Result<(), DerowinError> {
+    let mut verbosity = 0;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--ibus" => {}
+            "-v" | "--verbose" => verbosity += 1,
+            "-vv" => verbosity += 2,
+            "-vvv" => verbosity += 3,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => return Err(DerowinError::Usage(format!("Unexpected argument '{}'", arg))),
+        }
+    }
+    logging::init(verbosity, None, false)?;
+    let config = Config::load(None)?;
+    let address = ibus_address()?;
+    let ibus_error = |e: dbus::Error| DerowinError::Ibus(e.to_string());
+    let connection = Connection::new_address(&address).map_err(&ibus_error)?;
+    connection.request_name(BUS_NAME, false, true, true).map_err(&ibus_error)?;
+    info!("Serving {} on {}", BUS_NAME, address);
+    let mut server = Server {
+        engines: HashMap::new(),
+        created: 0,
+        converter: Converter {
+            escapes: Escapes::from_config(&config)?,
+            rules: None,
+        },
+        rule_file: RuleFile::from_config(&config)?,
+    };
+    server.reload_rules();
+    let mut rules_checked = Instant::now();
+    let channel = connection.channel();
+    loop {
+        if !channel.is_connected() {
+            info!("IBus went away");
+            return Ok(());
+        }
+        if let Some(call) = channel.blocking_pop_message(rules::CHECK_INTERVAL)
+            .map_err(&ibus_error)?
+        {
+            server.handle(channel, call);
+        }
+        if rules_checked.elapsed() >= rules::CHECK_INTERVAL {
+            rules_checked = Instant::now();
+            server.reload_rules();
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run() -> Result<(), DerowinError> {
+    Err(DerowinError::Usage(format!("IBus is only supported on Linux\n\n{}", USAGE)))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use derowin::compose::Action;
+
+    use super::{action_for, keyval, modifier};
+
+    #[test]
+    fn only_letters_start_a_word() {
+        assert_eq!(action_for(0x61, 0, false), Some(Action::Insert(String::from("a"))));
+        assert_eq!(action_for(0x41, modifier::SHIFT, false),
+            Some(Action::Insert(String::from("A"))));
+        assert_eq!(action_for(0x31, 0, false), None);
+        assert_eq!(action_for(0x2d, 0, false), None);
+        assert_eq!(action_for(0x2d, 0, true), Some(Action::Insert(String::from("-"))));
+        assert_eq!(action_for(0x20, 0, true), Some(Action::Insert(String::from(" "))));
+    }
+
+    #[test]
+    fn editing_keys_only_while_composing() {
+        for &key in &[keyval::BACKSPACE, keyval::DELETE, keyval::LEFT, keyval::RETURN] {
+            assert_eq!(action_for(key, 0, false), None);
+        }
+        assert_eq!(action_for(keyval::BACKSPACE, 0, true), Some(Action::Backspace));
+        assert_eq!(action_for(keyval::HOME, 0, true), Some(Action::Home));
+        assert_eq!(action_for(keyval::LOWER_Z, modifier::CONTROL, false), None);
+    }
+
+    #[test]
+    fn return_depends_on_the_modifiers() {
+        assert_eq!(action_for(keyval::RETURN, 0, true), Some(Action::Enter));
+        assert_eq!(action_for(keyval::KP_ENTER, modifier::SHIFT, true), Some(Action::Newline));
+        assert_eq!(action_for(keyval::RETURN, modifier::CONTROL, true), None);
+        assert_eq!(action_for(keyval::RETURN, modifier::CONTROL | modifier::SHIFT, true), None);
+    }
+
+    #[test]
+    fn control_z_undoes_and_redoes() {
+        let control = modifier::CONTROL;
+        assert_eq!(action_for(keyval::LOWER_Z, control, true), Some(Action::Undo));
+        assert_eq!(action_for(keyval::Z, control | modifier::SHIFT, true), Some(Action::Redo));
+        assert_eq!(action_for(0x61, control, true), None);
+    }
+
+    #[test]
+    fn leaves_alt_and_super_to_the_application() {
+        assert_eq!(action_for(0x61, modifier::ALT, true), None);
+        assert_eq!(action_for(0x61, modifier::SUPER, false), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! Copying and pasting through the platform's clipboard commands.
+
+use std::io::{self, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+use error::DerowinError;
+use logging::redact;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `pbcopy`/`pbpaste` on macOS.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Pasteboard,
+    /// No clipboard could be found, so copies and pastes do nothing.
+    Unavailable,
+}
+
+impl Backend {
+    /// Picks the clipboard commands to use for this session.
+    pub fn detect() -> Backend {
+        let backend = Backend::find();
+        match backend {
+            Backend::Unavailable => warn!("No clipboard command found; copying is disabled"),
+            _ => info!("Clipboard backend: {}", backend.name()),
+        }
+        backend
+    }
+
+    #[cfg(target_os = "macos")]
+    fn find() -> Backend {
+        Backend::Pasteboard
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn find() -> Backend {
+        Backend::Unavailable
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Backend::Pasteboard => "pbcopy",
+            Backend::Unavailable => "none",
+        }
+    }
+
+    pub fn copy(&self, text: &str) -> Result<(), DerowinError> {
+        let (program, args): (&'static str, &[&str]) = match *self {
+            Backend::Pasteboard => ("/usr/bin/pbcopy", &[]),
+            Backend::Unavailable => return Ok(()),
+        };
+        debug!("Copying {} with {}", redact(text), program);
+        let clip_err = |e: io::Error| DerowinError::Clipboard(program, e);
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(&clip_err)?;
+        // Taking stdin closes it once written, so the command sees the end.
+        match child.stdin.take() {
+            Some(mut stdin) => stdin.write_all(text.as_bytes()).map_err(&clip_err)?,
+            None => unreachable!(),
+        }
+        child.wait().and_then(check_status).map_err(&clip_err)
+    }
+
+    pub fn paste(&self) -> Result<String, DerowinError> {
+        let (program, args): (&'static str, &[&str]) = match *self {
+            Backend::Pasteboard => ("pbpaste", &[]),
+            Backend::Unavailable => return Ok(String::new()),
+        };
+        let clip_err = |e: io::Error| DerowinError::Clipboard(program, e);
+        let output = Command::new(program).args(args).output().map_err(&clip_err)?;
+        check_status(output.status).map_err(&clip_err)?;
+        let text = String::from_utf8_lossy(&output.stdout).to_string();
+        debug!("Pasted {} with {}", redact(&text), program);
+        Ok(text)
+    }
+}
+
+/// Turns a non-zero exit status into an error.
+pub fn check_status(status: ExitStatus) -> io::Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, format!("exited with {}", status)))
+    }
+}
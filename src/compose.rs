@@ -0,0 +1,323 @@
+//! What editing keys do to the buffer, and when it is committed, whichever
+//! frontend the keys came from.
+
+use dero::deromanize_escaped;
+
+use editor::Editor;
+use escape::Escapes;
+use mapping::Mapping;
+use romanize::{self, Style};
+use rules::Rules;
+use DeroMode;
+
+/// An editing key, once the frontend has made sense of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Insert(String),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Undo,
+    Redo,
+    /// Return, which commits in input and look-up mode, and starts a new
+    /// line otherwise.
+    Enter,
+    /// Shift+Return, which always starts a new line.
+    Newline,
+    /// The shortcut that commits in any mode.
+    Commit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// The key did nothing, so it can be passed on.
+    Ignored,
+    Edited,
+    /// The buffer should be committed. It is left to the frontend, since
+    /// only it knows where the text goes.
+    Commit,
+}
+
+pub fn apply(editor: &mut Editor, mode: DeroMode, action: Action) -> Effect {
+    let edited = match action {
+        Action::Insert(ref text) => {
+            editor.insert(text);
+            true
+        }
+        Action::Backspace => editor.backspace(),
+        Action::Delete => editor.delete(),
+        Action::Left => editor.move_left(),
+        Action::Right => editor.move_right(),
+        Action::Home => editor.move_home(),
+        Action::End => editor.move_end(),
+        Action::Undo => editor.undo(),
+        Action::Redo => editor.redo(),
+        Action::Enter if mode == DeroMode::Default => {
+            editor.insert("\n");
+            true
+        }
+        Action::Newline => {
+            editor.insert("\n");
+            true
+        }
+        Action::Enter | Action::Commit => {
+            return if editor.is_empty() { Effect::Ignored } else { Effect::Commit };
+        }
+    };
+    if edited { Effect::Edited } else { Effect::Ignored }
+}
+
+/// How text is converted: what is kept literal, and the user's rules
+/// around dero, if there are any. Each frontend keeps its own, and hands
+/// it to whatever converts.
+#[derive(Debug, Clone, Default)]
+pub struct Converter {
+    pub escapes: Escapes,
+    pub rules: Option<Rules>,
+}
+
+impl Converter {
+    /// Converts with dero alone, keeping text between the default marks.
+    pub fn new() -> Converter {
+        Converter::default()
+    }
+
+    /// Converts romanized text to Hangul.
+    pub fn convert(&self, text: &str) -> String {
+        self.convert_marked(text).0
+    }
+
+    /// Converts romanized text to Hangul, also returning the runs of
+    /// converted characters that were kept literal.
+    pub fn convert_marked(&self, text: &str) -> (String, Vec<(usize, usize)>) {
+        let mut converted = String::with_capacity(text.len() * 2);
+        let mut literal = Vec::new();
+        let mut count = 0;
+        for part in self.escapes.split(text) {
+            let part_text = &text[part.start..part.end];
+            let part_converted = match part_text {
+                "" => String::new(),
+                _ if part.literal => part_text.to_string(),
+                _ => self.convert_part(part_text),
+            };
+            let length = part_converted.chars().count();
+            if part.literal && length > 0 {
+                literal.push((count, count + length));
+            }
+            count += length;
+            converted.push_str(&part_converted);
+        }
+        (converted, literal)
+    }
+
+    /// Converts text outside of literal runs, with the rules if there are
+    /// any. dero's own escapes only work with the default marks; with
+    /// marks of the user's own, only runs of letters go to dero, so that
+    /// nothing else in the text is taken as an escape.
+    fn convert_part(&self, text: &str) -> String {
+        let dero = |text: &str| if self.escapes.marks.is_empty() {
+            deromanize_escaped(text)
+        } else {
+            convert_words(text)
+        };
+        match self.rules {
+            Some(ref rules) => rules.apply(text, dero),
+            None => dero(text),
+        }
+    }
+
+    /// Converts text the way the mode does: to Hangul, or in romanize
+    /// mode, to romanization in the given style.
+    pub fn convert_in(&self, mode: DeroMode, style: Style, text: &str) -> String {
+        match mode {
+            DeroMode::Romanize => romanize::romanize(text, style),
+            _ => self.convert(text),
+        }
+    }
+
+    /// The runs of converted characters that are kept literal, converting
+    /// the way the mode does. Romanize mode keeps nothing literal.
+    pub fn literal_in(&self, mode: DeroMode, text: &str) -> Vec<(usize, usize)> {
+        match mode {
+            DeroMode::Romanize => Vec::new(),
+            _ => self.convert_marked(text).1,
+        }
+    }
+
+    /// The converted buffer, and where the caret is in it, in characters.
+    /// The whole buffer is converted, as it would be committed, and the
+    /// caret placed through its mapping, so that a caret inside a syllable
+    /// doesn't split it.
+    pub fn preedit(&self, editor: &Editor) -> (String, usize) {
+        let mapping = Mapping::new(editor.text(), |text| self.convert(text));
+        let caret = mapping.converted_index(editor.caret());
+        (mapping.converted().to_string(), caret)
+    }
+
+    /// The runs of the text that are kept literal, by byte range, and with
+    /// their marks if they have them.
+    pub fn literal_parts(&self, text: &str) -> Vec<(usize, usize)> {
+        self.escapes.split(text).into_iter()
+            .filter(|part| part.literal)
+            .map(|part| part.marked.unwrap_or((part.start, part.end)))
+            .collect()
+    }
+
+    /// Whether the caret is in marked literal text.
+    pub fn in_literal(&self, editor: &Editor) -> bool {
+        self.escapes.literal_at(editor.text(), editor.caret()).is_some()
+    }
+
+    /// Ends the literal text the caret is in, dropping its marks if it is
+    /// empty, or otherwise starts literal text at the caret (around the
+    /// selection, if there is one). Literal text that was never closed is
+    /// closed at the caret.
+    pub fn toggle_literal(&self, editor: &mut Editor) {
+        let (open, close) = self.escapes.mark_pairs()[0];
+        match self.escapes.literal_at(editor.text(), editor.caret()) {
+            Some(part) => {
+                let (start, end) = part.marked.unwrap_or((part.start, part.end));
+                if part.start == part.end {
+                    editor.select(start, end);
+                    editor.backspace();
+                } else if end == part.end {
+                    editor.insert(close);
+                } else {
+                    editor.place_caret(end, false);
+                }
+            }
+            None => {
+                let marked = format!("{}{}{}", open, editor.selected_text(), close);
+                editor.insert(&marked);
+                let caret = editor.caret() - close.len();
+                editor.place_caret(caret, false);
+            }
+        }
+    }
+}
+
+/// Converts the runs of letters in text with dero, and leaves the rest as
+/// it is. Hyphens between letters stay in the run, since dero reads them
+/// as syllable breaks.
+fn convert_words(text: &str) -> String {
+    let mut converted = String::with_capacity(text.len() * 2);
+    let mut rest = text;
+    while !rest.is_empty() {
+        let in_word = |ch: char| ch.is_ascii_alphabetic() || ch == '-';
+        let length = match rest.find(|ch: char| !in_word(ch)) {
+            Some(0) => {
+                let other = rest.find(in_word).unwrap_or(rest.len());
+                converted.push_str(&rest[..other]);
+                rest = &rest[other..];
+                continue;
+            }
+            Some(length) => length,
+            None => rest.len(),
+        };
+        converted.push_str(&deromanize_escaped(&rest[..length]));
+        rest = &rest[length..];
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use dero::deromanize_escaped;
+
+    use editor::Editor;
+    use escape::Escapes;
+    use mapping::Mapping;
+    use super::{convert_words, Converter};
+
+    fn custom_marks() -> Converter {
+        let mut converter = Converter::new();
+        converter.escapes = Escapes {
+            marks: vec![(String::from("{"), String::from("}"))],
+            ..Escapes::new()
+        };
+        converter
+    }
+
+    fn editor(text: &str, caret: usize) -> Editor {
+        let mut editor = Editor::new();
+        editor.insert(text);
+        editor.place_caret(caret, false);
+        editor
+    }
+
+    #[test]
+    fn keeps_marked_text_literal() {
+        let converter = Converter::new();
+        let (converted, literal) = converter.convert_marked("han⟦ok⟧gug");
+        let expected = format!("{}ok{}", deromanize_escaped("han"), deromanize_escaped("gug"));
+        assert_eq!(converted, expected);
+        let start = deromanize_escaped("han").chars().count();
+        assert_eq!(literal, vec![(start, start + 2)]);
+    }
+
+    #[test]
+    fn preedit_is_what_would_be_committed() {
+        let converter = Converter::new();
+        for &(text, caret) in &[("ga⟦xyz⟧na", 7), ("ga⟦xyz⟧na", 1), ("ga⟦xy", 6), ("gan", 2)] {
+            let (preedit, index) = converter.preedit(&editor(text, caret));
+            assert_eq!(preedit, converter.convert(text));
+            let mapping = Mapping::new(text, |text| converter.convert(text));
+            assert_eq!(index, mapping.converted_index(caret));
+        }
+    }
+
+    #[test]
+    fn unclosed_literal_stays_literal() {
+        let converter = Converter::new();
+        let (converted, literal) = converter.convert_marked("ga⟦xy");
+        assert_eq!(converted, format!("{}xy", deromanize_escaped("ga")));
+        assert_eq!(literal.len(), 1);
+    }
+
+    #[test]
+    fn custom_marks_only_send_words_to_dero() {
+        let converter = custom_marks();
+        let text = "hangug-eo! {ok} (saram) 12";
+        let expected = format!("{}! ok ({}) 12", deromanize_escaped("hangug-eo"),
+            deromanize_escaped("saram"));
+        assert_eq!(converter.convert(text), expected);
+        assert_eq!(convert_words("  "), "  ");
+    }
+
+    #[test]
+    fn toggle_opens_and_leaves_literal_text() {
+        let converter = Converter::new();
+        let mut editor = editor("ab", 2);
+        converter.toggle_literal(&mut editor);
+        assert_eq!(editor.text(), "ab⟦⟧");
+        assert!(converter.in_literal(&editor));
+        editor.insert("c");
+        converter.toggle_literal(&mut editor);
+        assert_eq!(editor.text(), "ab⟦c⟧");
+        assert_eq!(editor.caret(), editor.text().len());
+        assert!(!converter.in_literal(&editor));
+    }
+
+    #[test]
+    fn toggle_drops_empty_marks() {
+        let converter = Converter::new();
+        let mut editor = editor("ab", 2);
+        converter.toggle_literal(&mut editor);
+        converter.toggle_literal(&mut editor);
+        assert_eq!(editor.text(), "ab");
+    }
+
+    #[test]
+    fn toggle_closes_an_unclosed_literal() {
+        let converter = custom_marks();
+        let mut editor = editor("a{bc", 3);
+        assert!(converter.in_literal(&editor));
+        converter.toggle_literal(&mut editor);
+        assert_eq!(editor.text(), "a{b}c");
+        assert_eq!(editor.caret(), 4);
+        assert!(!converter.in_literal(&editor));
+    }
+}
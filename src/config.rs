@@ -0,0 +1,121 @@
+//! The configuration file.
+//!
+//! The file is read from `$XDG_CONFIG_HOME/derowin/config` (falling back to
+//! `~/.config`), or the path given with `--config`. Each line is a
+//! `key = value` pair, and lines starting with `#` are comments:
+//!
+//! ```text
+//! theme = dark
+//! theme.caret-shape = bar
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use error::DerowinError;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Where each value came from (a file and line, or the command line),
+    /// for error messages.
+    values: BTreeMap<String, (String, String)>,
+}
+
+impl Config {
+    /// Reads the given config file, or the default one if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Config, DerowinError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(ref path) if path.is_file() => path.clone(),
+                _ => {
+                    debug!("No config file found");
+                    return Ok(Config::default());
+                }
+            },
+        };
+        let mut contents = String::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| io_error(&path, e))?;
+        info!("Using config file {}", path.display());
+        Config::parse(&contents, &path.display().to_string())
+    }
+
+    pub fn parse(contents: &str, source: &str) -> Result<Config, DerowinError> {
+        let mut config = Config::default();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let origin = format!("{}:{}", source, i + 1);
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) if !key.is_empty() => value.trim(),
+                _ => return Err(DerowinError::Config(format!("{}: expected 'key = value'", origin))),
+            };
+            config.values.insert(key.to_string(), (value.to_string(), origin));
+        }
+        Ok(config)
+    }
+
+    /// Sets a value from the command line, overriding the file.
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), (value.to_string(), String::from("command line")));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|&(ref value, _)| value.as_str())
+    }
+
+    /// Reads a value with `FromStr`, failing with the line it came from.
+    pub fn parse_value<T: FromStr>(&self, key: &str) -> Result<Option<T>, DerowinError> {
+        self.parse_with(key, |value| value.parse().ok())
+    }
+
+    /// Reads a value with a custom parser, failing with the line it came
+    /// from.
+    pub fn parse_with<T, F>(&self, key: &str, parse: F) -> Result<Option<T>, DerowinError>
+        where F: FnOnce(&str) -> Option<T>
+    {
+        match self.values.get(key) {
+            Some(&(ref value, ref origin)) => match parse(value) {
+                Some(parsed) => Ok(Some(parsed)),
+                None => Err(DerowinError::Config(
+                    format!("{}: invalid value '{}' for '{}'", origin, value, key))),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// The `derowin` directory under `$XDG_CONFIG_HOME` or `~/.config`.
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("derowin")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("derowin")),
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config"))
+}
+
+/// Parses `on`/`off` style booleans.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn io_error(path: &Path, err: io::Error) -> DerowinError {
+    DerowinError::Config(format!("Could not read '{}': {}", path.display(), err))
+}
@@ -0,0 +1,19 @@
+//! Requests to the main loop that come from outside the SDL event queue,
+//! like the tray menu, the global shortcut and the control socket.
+
+use std::sync::mpsc::Sender;
+
+use ipc::{Reply, Request};
+use DeroMode;
+
+#[derive(Debug)]
+pub enum Control {
+    /// Hides the window if it is shown, or shows and focuses it.
+    ToggleWindow,
+    /// Shows the window if it is hidden, and brings it to the front.
+    ShowWindow,
+    SetMode(DeroMode),
+    /// A control socket request, answered on the sender.
+    Ipc(Request, Sender<Reply>),
+    Quit,
+}
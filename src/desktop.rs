@@ -0,0 +1,60 @@
+//! Desktop integration outside the window: the tray icon and the global
+//! shortcut. Both are only available on Linux for now.
+//!
+//! The tray is on unless `tray = off` is set, and the shortcut is set with
+//! the `hotkey` config key (like `hotkey = ctrl+alt+k`).
+
+use std::sync::mpsc::Sender;
+
+use config::{self, Config};
+use control::Control;
+use error::DerowinError;
+#[cfg(target_os = "linux")]
+use hotkey::{self, Shortcut};
+#[cfg(target_os = "linux")]
+use tray::Tray;
+use DeroMode;
+
+pub struct Desktop {
+    #[cfg(target_os = "linux")]
+    tray: Option<Tray>,
+}
+
+impl Desktop {
+    #[cfg(target_os = "linux")]
+    pub fn start(config: &Config, sender: &Sender<Control>, mode: DeroMode)
+        -> Result<Desktop, DerowinError>
+    {
+        let tray = if config.parse_with("tray", config::parse_bool)?.unwrap_or(true) {
+            Some(Tray::spawn(sender.clone(), mode))
+        } else {
+            None
+        };
+        if let Some(shortcut) = config.parse_with("hotkey", Shortcut::parse)? {
+            hotkey::spawn(shortcut, sender.clone());
+        }
+        Ok(Desktop { tray: tray })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn start(config: &Config, _sender: &Sender<Control>, _mode: DeroMode)
+        -> Result<Desktop, DerowinError>
+    {
+        if config.get("hotkey").is_some() {
+            warn!("Global shortcuts are only supported on Linux");
+        }
+        // Still checked, so that a shared config file has no surprises.
+        config.parse_with("tray", config::parse_bool)?;
+        Ok(Desktop {})
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_mode(&self, mode: DeroMode) {
+        if let Some(ref tray) = self.tray {
+            tray.set_mode(mode);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn set_mode(&self, _mode: DeroMode) {}
+}
@@ -0,0 +1,392 @@
+//! The romanized text being edited, with a caret and undo history.
+
+/// How many undo steps are kept before the oldest ones are dropped.
+const MAX_UNDO: usize = 200;
+
+/// A saved state of the buffer, used for undo/redo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub text: String,
+    pub caret: usize,
+}
+
+/// The kind of the last edit, so that runs of typing or deleting can be
+/// undone as a single step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditKind {
+    Insert,
+    Delete,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct Editor {
+    text: String,
+    caret: usize,
+    /// The other end of the selection, from the caret.
+    anchor: Option<usize>,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    last_edit: Option<EditKind>,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor::from_parts(String::new(), 0, Vec::new(), Vec::new())
+    }
+
+    /// Rebuilds an editor from saved parts. Carets that don't fall on a
+    /// character boundary are moved to the end of their text.
+    pub fn from_parts(text: String, caret: usize, undo: Vec<Snapshot>, redo: Vec<Snapshot>)
+        -> Editor
+    {
+        let fix = |snapshot: Snapshot| {
+            let caret = valid_caret(&snapshot.text, snapshot.caret);
+            Snapshot { text: snapshot.text, caret: caret }
+        };
+        let caret = valid_caret(&text, caret);
+        Editor {
+            text: text,
+            caret: caret,
+            anchor: None,
+            undo: undo.into_iter().map(&fix).collect(),
+            redo: redo.into_iter().map(&fix).collect(),
+            last_edit: None,
+        }
+    }
+
+    #[inline]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The byte position of the caret in the text.
+    #[inline]
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    #[inline]
+    pub fn before_caret(&self) -> &str {
+        &self.text[..self.caret]
+    }
+
+    #[inline]
+    pub fn after_caret(&self) -> &str {
+        &self.text[self.caret..]
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// The byte range of the selected text, if any is selected.
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        match self.anchor {
+            Some(anchor) if anchor < self.caret => Some((anchor, self.caret)),
+            Some(anchor) if anchor > self.caret => Some((self.caret, anchor)),
+            _ => None,
+        }
+    }
+
+    pub fn selected_text(&self) -> &str {
+        match self.selection() {
+            Some((start, end)) => &self.text[start..end],
+            None => "",
+        }
+    }
+
+    /// Selects the text between two byte positions, with the caret at
+    /// `end`.
+    pub fn select(&mut self, start: usize, end: usize) {
+        self.anchor = Some(valid_caret(&self.text, start));
+        self.caret = valid_caret(&self.text, end);
+        self.last_edit = None;
+    }
+
+    /// Moves the caret to a byte position, extending the selection to it
+    /// if asked, or else dropping the selection.
+    pub fn place_caret(&mut self, caret: usize, extend: bool) -> bool {
+        let caret = valid_caret(&self.text, caret);
+        let selection = self.selection();
+        if extend {
+            if self.anchor.is_none() {
+                self.anchor = Some(self.caret);
+            }
+        } else {
+            self.anchor = None;
+        }
+        let moved = caret != self.caret;
+        self.caret = caret;
+        self.last_edit = None;
+        moved || selection != self.selection()
+    }
+
+    /// The undo and redo stacks, oldest first.
+    pub fn history(&self) -> (&[Snapshot], &[Snapshot]) {
+        (&self.undo, &self.redo)
+    }
+
+    /// Inserts text at the caret, replacing the selection.
+    pub fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.remove_selection() {
+            // Undone along with the text that replaced it.
+            self.last_edit = Some(EditKind::Insert);
+        }
+        self.record(EditKind::Insert);
+        self.text.insert_str(self.caret, text);
+        self.caret += text.len();
+        // Words are undone one at a time.
+        if text.chars().any(char::is_whitespace) {
+            self.last_edit = None;
+        }
+    }
+
+    /// Removes the selection, or the character before the caret.
+    pub fn backspace(&mut self) -> bool {
+        if self.remove_selection() {
+            return true;
+        }
+        match self.before_caret().chars().next_back() {
+            Some(ch) => {
+                self.record(EditKind::Delete);
+                self.caret -= ch.len_utf8();
+                self.text.remove(self.caret);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the selection, or the character after the caret.
+    pub fn delete(&mut self) -> bool {
+        if self.remove_selection() {
+            return true;
+        }
+        if self.caret == self.text.len() {
+            return false;
+        }
+        self.record(EditKind::Delete);
+        self.text.remove(self.caret);
+        true
+    }
+
+    /// Empties the buffer and returns what it contained.
+    pub fn take(&mut self) -> String {
+        if self.text.is_empty() {
+            return String::new();
+        }
+        self.record(EditKind::Other);
+        self.caret = 0;
+        self.anchor = None;
+        self.last_edit = None;
+        ::std::mem::replace(&mut self.text, String::new())
+    }
+
+    /// Moves the caret back a character, or to the start of the
+    /// selection.
+    pub fn move_left(&mut self) -> bool {
+        if let Some((start, _)) = self.selection() {
+            self.place_caret(start, false);
+            return true;
+        }
+        self.anchor = None;
+        match self.before_caret().chars().next_back() {
+            Some(ch) => {
+                self.caret -= ch.len_utf8();
+                self.last_edit = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the caret on a character, or to the end of the selection.
+    pub fn move_right(&mut self) -> bool {
+        if let Some((_, end)) = self.selection() {
+            self.place_caret(end, false);
+            return true;
+        }
+        self.anchor = None;
+        match self.after_caret().chars().next() {
+            Some(ch) => {
+                self.caret += ch.len_utf8();
+                self.last_edit = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the caret to the start of its line.
+    pub fn move_home(&mut self) -> bool {
+        let start = self.before_caret().rfind('\n').map(|i| i + 1).unwrap_or(0);
+        self.move_to(start)
+    }
+
+    /// Moves the caret to the end of its line.
+    pub fn move_end(&mut self) -> bool {
+        let end = self.after_caret().find('\n')
+            .map(|i| self.caret + i)
+            .unwrap_or(self.text.len());
+        self.move_to(end)
+    }
+
+    fn move_to(&mut self, caret: usize) -> bool {
+        self.place_caret(caret, false)
+    }
+
+    /// Removes the selected text, returning whether there was any.
+    fn remove_selection(&mut self) -> bool {
+        match self.selection() {
+            Some((start, end)) => {
+                self.record(EditKind::Other);
+                self.text.drain(start..end);
+                self.caret = start;
+                self.anchor = None;
+                true
+            }
+            None => {
+                self.anchor = None;
+                false
+            }
+        }
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(snapshot) => {
+                let current = self.restore(snapshot);
+                self.redo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(snapshot) => {
+                let current = self.restore(snapshot);
+                self.undo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        self.last_edit = None;
+        self.anchor = None;
+        let text = ::std::mem::replace(&mut self.text, snapshot.text);
+        let caret = ::std::mem::replace(&mut self.caret, snapshot.caret);
+        Snapshot { text: text, caret: caret }
+    }
+
+    /// Saves an undo step, unless the edit continues a run of the same kind.
+    fn record(&mut self, kind: EditKind) {
+        if kind == EditKind::Other || self.last_edit != Some(kind) {
+            self.undo.push(Snapshot { text: self.text.clone(), caret: self.caret });
+            if self.undo.len() > MAX_UNDO {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+        self.last_edit = Some(kind);
+    }
+}
+
+fn valid_caret(text: &str, caret: usize) -> usize {
+    if text.is_char_boundary(caret) {
+        caret
+    } else {
+        text.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Editor, Snapshot};
+
+    fn typed(text: &str) -> Editor {
+        let mut editor = Editor::new();
+        for ch in text.chars() {
+            editor.insert(&ch.to_string());
+        }
+        editor
+    }
+
+    #[test]
+    fn undoes_a_word_at_a_time() {
+        let mut editor = typed("han gug");
+        assert!(editor.undo());
+        assert_eq!(editor.text(), "han ");
+        assert!(editor.undo());
+        assert_eq!(editor.text(), "");
+        assert!(!editor.undo());
+        assert!(editor.redo());
+        assert!(editor.redo());
+        assert_eq!(editor.text(), "han gug");
+        assert_eq!(editor.caret(), 7);
+        assert!(!editor.redo());
+    }
+
+    #[test]
+    fn an_edit_drops_what_could_be_redone() {
+        let mut editor = typed("hangug");
+        editor.backspace();
+        editor.backspace();
+        assert_eq!(editor.text(), "hang");
+        assert!(editor.undo());
+        assert_eq!(editor.text(), "hangug");
+        editor.insert("eo");
+        assert!(!editor.redo());
+        assert!(editor.undo());
+        assert_eq!(editor.text(), "hangug");
+    }
+
+    #[test]
+    fn typing_replaces_the_selection() {
+        let mut editor = typed("hangug");
+        editor.select(3, 6);
+        assert_eq!(editor.selected_text(), "gug");
+        editor.insert("a");
+        assert_eq!(editor.text(), "hana");
+        assert_eq!(editor.selection(), None);
+        assert!(editor.undo());
+        assert_eq!(editor.text(), "hangug");
+    }
+
+    #[test]
+    fn moving_collapses_the_selection() {
+        let mut editor = typed("hangug");
+        editor.select(5, 1);
+        assert_eq!(editor.selection(), Some((1, 5)));
+        assert!(editor.move_right());
+        assert_eq!((editor.caret(), editor.selection()), (5, None));
+        editor.place_caret(2, true);
+        assert_eq!(editor.selection(), Some((2, 5)));
+        assert!(editor.backspace());
+        assert_eq!(editor.text(), "hag");
+    }
+
+    #[test]
+    fn keeps_the_caret_on_a_character() {
+        let snapshot = Snapshot { text: String::from("한"), caret: 1 };
+        let mut editor = Editor::from_parts(String::from("a한"), 2, vec![snapshot], Vec::new());
+        assert_eq!(editor.caret(), 4);
+        assert!(editor.undo());
+        assert_eq!(editor.caret(), 3);
+        editor.place_caret(100, false);
+        assert_eq!(editor.caret(), 3);
+        editor.select(0, 2);
+        assert_eq!(editor.selection(), Some((0, 3)));
+        assert!(editor.move_left());
+        assert_eq!(editor.caret(), 0);
+        assert!(!editor.move_left());
+    }
+}
@@ -0,0 +1,108 @@
+//! The things that can go wrong in derowin.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum DerowinError {
+    /// The command line arguments were wrong.
+    Usage(String),
+    /// The log file could not be opened.
+    LogFile(String, io::Error),
+    /// The config file could not be read, or had a bad value.
+    Config(String),
+    /// SDL or one of its subsystems could not be initialized.
+    Sdl(String),
+    /// The window or its renderer could not be created.
+    Window(String),
+    /// The font file could not be read.
+    FontIo(String, io::Error),
+    /// The font file was read, but held no usable font.
+    FontFormat(String),
+    /// None of the font files that were tried exist.
+    FontMissing(Vec<String>),
+    /// Text could not be rendered, or drawn to the window.
+    Render(String),
+    /// A clipboard command could not be run, or failed.
+    Clipboard(&'static str, io::Error),
+    /// The dictionary could not be opened.
+    Lookup(io::Error),
+    /// Text could not be typed into the focused window.
+    Typing(&'static str, String),
+    /// The input method engine could not talk to IBus.
+    Ibus(String),
+    /// The control socket could not be reached, or refused a request.
+    Ipc(String),
+    /// A file could not be read, or its conversion written.
+    File(String, io::Error),
+    /// A commit hook could not be run, or failed.
+    Hook(String, io::Error),
+}
+
+impl DerowinError {
+    /// The process exit code for when the error ends the program.
+    pub fn exit_code(&self) -> i32 {
+        use self::DerowinError::*;
+        match *self {
+            Usage(_) => 64,
+            Sdl(_) => 2,
+            Window(_) => 3,
+            FontIo(..) | FontFormat(_) | FontMissing(_) => 4,
+            LogFile(..) => 5,
+            Config(_) => 6,
+            Ibus(_) => 7,
+            Ipc(_) => 8,
+            File(..) => 9,
+            Render(_) | Clipboard(..) | Lookup(_) | Typing(..) | Hook(..) => 1,
+        }
+    }
+}
+
+impl fmt::Display for DerowinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DerowinError::*;
+        match *self {
+            Usage(ref reason) => write!(f, "{}", reason),
+            LogFile(ref path, ref err) => write!(f, "Could not open log file '{}': {}", path, err),
+            Config(ref reason) => write!(f, "Config error: {}", reason),
+            Sdl(ref reason) => write!(f, "Could not initialize SDL: {}", reason),
+            Window(ref reason) => write!(f, "Could not create the window: {}", reason),
+            FontIo(ref path, ref err) => write!(f, "Could not read font '{}': {}", path, err),
+            FontFormat(ref path) => write!(f, "No usable font found in '{}'", path),
+            FontMissing(ref paths) => write!(f, "No font found (tried {})", paths.join(", ")),
+            Render(ref reason) => write!(f, "Could not draw text: {}", reason),
+            Clipboard(command, ref err) => write!(f, "Clipboard error ({}): {}", command, err),
+            Lookup(ref err) => write!(f, "Could not open the dictionary: {}", err),
+            Typing(typer, ref reason) => write!(f, "Could not type the text ({}): {}", typer, reason),
+            Ibus(ref reason) => write!(f, "IBus error: {}", reason),
+            Ipc(ref reason) => write!(f, "Control socket error: {}", reason),
+            File(ref path, ref err) => write!(f, "File error ('{}'): {}", path, err),
+            Hook(ref command, ref err) => write!(f, "Commit hook failed ('{}'): {}", command, err),
+        }
+    }
+}
+
+impl Error for DerowinError {
+    fn description(&self) -> &str {
+        use self::DerowinError::*;
+        match *self {
+            Usage(_) => "invalid arguments",
+            LogFile(..) => "could not open log file",
+            Config(_) => "config error",
+            Sdl(_) => "could not initialize SDL",
+            Window(_) => "could not create the window",
+            FontIo(..) => "could not read font",
+            FontFormat(_) => "no usable font",
+            FontMissing(_) => "no font found",
+            Render(_) => "could not draw text",
+            Clipboard(..) => "clipboard error",
+            Lookup(_) => "could not open the dictionary",
+            Typing(..) => "could not type the text",
+            Ibus(_) => "IBus error",
+            Ipc(_) => "control socket error",
+            File(..) => "file error",
+            Hook(..) => "commit hook failed",
+        }
+    }
+}
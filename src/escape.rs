@@ -0,0 +1,229 @@
+//! Text that is kept as it is instead of being converted.
+//!
+//! Literal text goes between a pair of marks, which are dropped from the
+//! converted text. The marks are derowin's own, and can be set so that
+//! they don't clash with the text. With the default marks, dero's own
+//! escapes still work in the rest, which goes to `deromanize_escaped`;
+//! with marks of the user's own, only words go to dero. URLs, numbers and
+//! email addresses can also be passed through without marks.
+
+use config::{self, Config};
+use error::DerowinError;
+
+/// The marks used when none are set, which are rare in ordinary text.
+pub const DEFAULT_MARKS: (&'static str, &'static str) = ("⟦", "⟧");
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Escapes {
+    /// Pairs of marks around literal text, or none for the default ones.
+    /// The first pair is the one that literal input is put between.
+    pub marks: Vec<(String, String)>,
+    pub urls: bool,
+    pub numbers: bool,
+    pub emails: bool,
+}
+
+/// A run of text, by byte range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part {
+    pub start: usize,
+    pub end: usize,
+    pub literal: bool,
+    /// Where the marks around literal text start and end, if it has them.
+    pub marked: Option<(usize, usize)>,
+}
+
+impl Escapes {
+    /// Only the default marks.
+    pub fn new() -> Escapes {
+        Escapes {
+            marks: Vec::new(),
+            urls: false,
+            numbers: false,
+            emails: false,
+        }
+    }
+
+    /// Reads `escape.marks`, a comma-separated list of space-separated
+    /// pairs, and the `escape.*` passthrough flags.
+    pub fn from_config(config: &Config) -> Result<Escapes, DerowinError> {
+        let marks = config.parse_with("escape.marks", |value| {
+            value.split(',')
+                .map(|pair| {
+                    let mut marks = pair.split_whitespace();
+                    match (marks.next(), marks.next(), marks.next()) {
+                        (Some(open), Some(close), None) => {
+                            Some((open.to_string(), close.to_string()))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect::<Option<Vec<_>>>()
+        })?;
+        let flag = |key| config.parse_with(key, config::parse_bool).map(|on| on.unwrap_or(false));
+        Ok(Escapes {
+            marks: marks.unwrap_or_default(),
+            urls: flag("escape.urls")?,
+            numbers: flag("escape.numbers")?,
+            emails: flag("escape.emails")?,
+        })
+    }
+
+    /// The pairs of marks in use.
+    pub fn mark_pairs(&self) -> Vec<(&str, &str)> {
+        if self.marks.is_empty() {
+            vec![DEFAULT_MARKS]
+        } else {
+            self.marks.iter().map(|&(ref open, ref close)| (open.as_str(), close.as_str())).collect()
+        }
+    }
+
+    /// Splits text into runs to convert and literal runs. A literal run
+    /// that isn't closed goes on to the end. Close marks that weren't
+    /// opened are converted like any other text.
+    pub fn split(&self, text: &str) -> Vec<Part> {
+        let pairs = self.mark_pairs();
+        let to_convert = |start, end| Part { start: start, end: end, literal: false, marked: None };
+        let mut parts = Vec::new();
+        let mut convert_start = 0;
+        let mut i = 0;
+        while i < text.len() {
+            let rest = &text[i..];
+            let at_word = i == 0 || text[..i].chars().next_back().map_or(true, char::is_whitespace);
+            let literal = match pairs.iter().find(|&&(open, _)| rest.starts_with(open)) {
+                Some(&(open, close)) => {
+                    let start = i + open.len();
+                    let (end, outer_end) = match text[start..].find(close) {
+                        Some(found) => (start + found, start + found + close.len()),
+                        None => (text.len(), text.len()),
+                    };
+                    let marked = Some((i, outer_end));
+                    Some(Part { start: start, end: end, literal: true, marked: marked })
+                }
+                None if at_word => self.passthrough(rest)
+                    .map(|length| Part { start: i, end: i + length, literal: true, marked: None }),
+                None => None,
+            };
+            match literal {
+                Some(part) => {
+                    if convert_start < i {
+                        parts.push(to_convert(convert_start, i));
+                    }
+                    let outer_end = part.marked.map_or(part.end, |(_, end)| end);
+                    parts.push(part);
+                    convert_start = outer_end;
+                    i = outer_end;
+                }
+                None => i += rest.chars().next().map_or(1, char::len_utf8),
+            }
+        }
+        if convert_start < text.len() || parts.is_empty() {
+            parts.push(to_convert(convert_start, text.len()));
+        }
+        parts
+    }
+
+    /// The marked literal run that a caret is in, between its marks.
+    pub fn literal_at(&self, text: &str, caret: usize) -> Option<Part> {
+        self.split(text).into_iter()
+            .find(|part| part.marked.is_some() && part.start <= caret && caret <= part.end)
+    }
+
+    /// How long the word at the start of the text is, if it is passed
+    /// through. Punctuation after it is left out.
+    fn passthrough(&self, rest: &str) -> Option<usize> {
+        let word = rest.split(char::is_whitespace).next().unwrap_or("");
+        let word = word.trim_end_matches(|ch: char| ".,;:!?)\"'".contains(ch));
+        let passed = (self.urls && is_url(word))
+            || (self.numbers && is_number(word))
+            || (self.emails && is_email(word));
+        if passed && !word.is_empty() { Some(word.len()) } else { None }
+    }
+}
+
+fn is_url(word: &str) -> bool {
+    word.starts_with("http://") || word.starts_with("https://") || word.starts_with("www.")
+}
+
+fn is_number(word: &str) -> bool {
+    word.starts_with(|ch: char| ch.is_ascii_digit())
+        && word.chars().all(|ch| ch.is_ascii_digit() || ch == '.' || ch == ',')
+}
+
+fn is_email(word: &str) -> bool {
+    let mut halves = word.splitn(2, '@');
+    match (halves.next(), halves.next()) {
+        (Some(user), Some(domain)) => !user.is_empty() && domain.contains('.')
+            && !domain.starts_with('.') && !domain.ends_with('.'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Escapes, Part};
+
+    fn custom(open: &str, close: &str) -> Escapes {
+        Escapes { marks: vec![(open.to_string(), close.to_string())], ..Escapes::new() }
+    }
+
+    /// The text of each run, and whether it is literal.
+    fn runs<'a>(escapes: &Escapes, text: &'a str) -> Vec<(&'a str, bool)> {
+        escapes.split(text).into_iter()
+            .map(|part| (&text[part.start..part.end], part.literal))
+            .collect()
+    }
+
+    #[test]
+    fn splits_at_the_default_marks() {
+        let escapes = Escapes::new();
+        assert_eq!(runs(&escapes, "a⟦b⟧c"), vec![("a", false), ("b", true), ("c", false)]);
+        assert_eq!(escapes.split("⟦b⟧")[0],
+            Part { start: 3, end: 4, literal: true, marked: Some((0, 7)) });
+        assert_eq!(runs(&escapes, ""), vec![("", false)]);
+    }
+
+    #[test]
+    fn splits_at_custom_marks() {
+        let escapes = custom("{{", "}}");
+        assert_eq!(runs(&escapes, "a {{b}} c ⟦d⟧"),
+            vec![("a ", false), ("b", true), (" c ⟦d⟧", false)]);
+    }
+
+    #[test]
+    fn unclosed_literal_goes_to_the_end() {
+        let escapes = Escapes::new();
+        assert_eq!(runs(&escapes, "a⟦bc"), vec![("a", false), ("bc", true)]);
+        assert_eq!(escapes.split("a⟦bc")[1].marked, Some((1, 6)));
+    }
+
+    #[test]
+    fn unopened_close_mark_is_converted() {
+        let escapes = Escapes::new();
+        assert_eq!(runs(&escapes, "ab⟧c"), vec![("ab⟧c", false)]);
+        assert_eq!(runs(&escapes, "a⟧b⟦c⟧"), vec![("a⟧b", false), ("c", true)]);
+    }
+
+    #[test]
+    fn passes_through_urls_numbers_and_emails() {
+        let escapes = Escapes { urls: true, numbers: true, emails: true, ..Escapes::new() };
+        assert_eq!(runs(&escapes, "see https://a.kr, ok"),
+            vec![("see ", false), ("https://a.kr", true), (", ok", false)]);
+        assert_eq!(runs(&escapes, "1,000 won"), vec![("1,000", true), (" won", false)]);
+        assert_eq!(runs(&escapes, "to a@b.kr."),
+            vec![("to ", false), ("a@b.kr", true), (".", false)]);
+        // Only whole words pass through.
+        assert_eq!(runs(&escapes, "a1 b@c"), vec![("a1 b@c", false)]);
+        assert_eq!(runs(&Escapes::new(), "www.a.kr"), vec![("www.a.kr", false)]);
+    }
+
+    #[test]
+    fn finds_the_literal_run_at_a_caret() {
+        let escapes = Escapes::new();
+        let text = "a⟦bc⟧d";
+        assert_eq!(escapes.literal_at(text, 4).map(|part| (part.start, part.end)), Some((4, 6)));
+        assert_eq!(escapes.literal_at(text, 6).map(|part| part.start), Some(4));
+        assert!(escapes.literal_at(text, 0).is_none());
+        assert!(escapes.literal_at(text, text.len()).is_none());
+    }
+}
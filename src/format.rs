@@ -0,0 +1,425 @@
+//! Which parts of a document are prose, so that converting it leaves its
+//! syntax alone.
+//!
+//! - Markdown: everything but front matter, fenced code, code spans, link
+//!   targets, URLs and HTML tags
+//! - Subtitles (SRT and WebVTT): only cue text, without its tags
+//! - CSV and TSV: only the chosen columns, and not the header row when
+//!   there is one (as `--header` says, or naming a column implies)
+
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    Markdown,
+    Subtitles,
+    Csv,
+    Tsv,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::Plain => "plain",
+            Format::Markdown => "markdown",
+            Format::Subtitles => "subtitles",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "plain" | "text" => Some(Format::Plain),
+            "markdown" | "md" => Some(Format::Markdown),
+            "subtitles" | "srt" | "vtt" => Some(Format::Subtitles),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from a file's extension.
+    pub fn from_path(path: &Path) -> Format {
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_ref().map(|extension| extension.as_str()) {
+            Some("md") | Some("markdown") | Some("mkd") => Format::Markdown,
+            Some("srt") | Some("vtt") => Format::Subtitles,
+            Some("csv") => Format::Csv,
+            Some("tsv") | Some("tab") => Format::Tsv,
+            _ => Format::Plain,
+        }
+    }
+}
+
+/// A column of a CSV or TSV file, by number (from 1) or by its name in
+/// the header row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Number(usize),
+    Name(String),
+}
+
+/// Parses a comma-separated list of columns, like `2,meaning`.
+pub fn parse_columns(list: &str) -> Option<Vec<Column>> {
+    list.split(',')
+        .map(|column| column.trim())
+        .map(|column| match column.parse::<usize>() {
+            Ok(0) => None,
+            Ok(number) => Some(Column::Number(number)),
+            Err(_) if column.is_empty() => None,
+            Err(_) => Some(Column::Name(column.to_string())),
+        })
+        .collect()
+}
+
+/// Goes through a document a line at a time, handing its prose to a
+/// converter and keeping everything else as it is.
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    format: Format,
+    line: usize,
+    /// The line that ends the front matter, while in it.
+    front_matter: Option<&'static str>,
+    /// The character and length of the fence around a code block, while
+    /// in it.
+    fence: Option<(char, usize)>,
+    /// Whether the last line was a cue's timing or text.
+    in_cue: bool,
+    /// The columns to convert, by index, or all of them if `None`.
+    selected: Option<Vec<usize>>,
+    /// Columns to look up in the header row.
+    names: Vec<String>,
+    header: bool,
+    row: usize,
+    field: usize,
+    quoted: bool,
+}
+
+impl Scanner {
+    /// `columns` says which CSV or TSV columns to convert (all of them if
+    /// empty), and `header` whether the first row is a header. Naming a
+    /// column means there is one.
+    pub fn new(format: Format, columns: &[Column], header: bool) -> Scanner {
+        let mut selected = Vec::new();
+        let mut names = Vec::new();
+        for column in columns {
+            match *column {
+                Column::Number(number) => selected.push(number - 1),
+                Column::Name(ref name) => names.push(name.clone()),
+            }
+        }
+        Scanner {
+            format: format,
+            line: 0,
+            front_matter: None,
+            fence: None,
+            in_cue: false,
+            selected: if columns.is_empty() { None } else { Some(selected) },
+            header: header || !names.is_empty(),
+            names: names,
+            row: 0,
+            field: 0,
+            quoted: false,
+        }
+    }
+
+    /// Converts the prose in a line, which is given without its ending.
+    pub fn line<F: FnMut(&str) -> String>(&mut self, line: &str, mut convert: F) -> String {
+        self.line_at(line, &mut |_, prose| convert(prose))
+    }
+
+    /// Where the prose is in a line, by byte range, leaving the line as it
+    /// is.
+    pub fn prose(&mut self, line: &str) -> Vec<(usize, usize)> {
+        let mut prose = Vec::new();
+        self.line_at(line, &mut |start, text| {
+            prose.push((start, start + text.len()));
+            text.to_string()
+        });
+        prose
+    }
+
+    /// Converts the prose in a line, giving `convert` where each piece of
+    /// it starts too.
+    fn line_at<F: FnMut(usize, &str) -> String>(&mut self, line: &str, convert: &mut F)
+        -> String
+    {
+        let converted = match self.format {
+            Format::Plain => convert(0, line),
+            Format::Markdown => self.markdown_line(line, convert),
+            Format::Subtitles => self.subtitle_line(line, convert),
+            Format::Csv => self.delimited_line(line, ',', convert),
+            Format::Tsv => self.delimited_line(line, '\t', convert),
+        };
+        self.line += 1;
+        converted
+    }
+
+    fn markdown_line<F: FnMut(usize, &str) -> String>(&mut self, line: &str, convert: &mut F)
+        -> String
+    {
+        let trimmed = line.trim_end();
+        if let Some(end) = self.front_matter {
+            if trimmed == end || (end == "---" && trimmed == "...") {
+                self.front_matter = None;
+            }
+            return line.to_string();
+        }
+        if self.line == 0 && (trimmed == "---" || trimmed == "+++") {
+            self.front_matter = Some(if trimmed == "---" { "---" } else { "+++" });
+            return line.to_string();
+        }
+        // Fences may be indented by up to three spaces.
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let fence = if indent <= 3 { fence_of(&line[indent..]) } else { None };
+        match (self.fence, fence) {
+            (None, Some(fence)) => {
+                self.fence = Some(fence);
+                line.to_string()
+            }
+            (Some((ch, length)), Some((closing, closing_length)))
+                if ch == closing && closing_length >= length
+                    && line[indent..].trim_end().chars().all(|c| c == ch) =>
+            {
+                self.fence = None;
+                line.to_string()
+            }
+            (Some(_), _) => line.to_string(),
+            // Link reference definitions are all target.
+            (None, None) if is_reference_definition(line) => line.to_string(),
+            (None, None) => convert_outside(line, convert, markdown_syntax),
+        }
+    }
+
+    fn subtitle_line<F: FnMut(usize, &str) -> String>(&mut self, line: &str, convert: &mut F)
+        -> String
+    {
+        if line.trim().is_empty() {
+            self.in_cue = false;
+            line.to_string()
+        } else if line.contains("-->") {
+            // The timing, which the cue text follows.
+            self.in_cue = true;
+            line.to_string()
+        } else if self.in_cue {
+            convert_outside(line, convert, subtitle_tag)
+        } else {
+            // Cue numbers and identifiers, and the WebVTT header, notes
+            // and styles.
+            line.to_string()
+        }
+    }
+
+    fn delimited_line<F: FnMut(usize, &str) -> String>(&mut self, line: &str, separator: char,
+        convert: &mut F)
+        -> String
+    {
+        // A quoted field can go on over several lines.
+        if !self.quoted {
+            self.field = 0;
+        }
+        let mut converted = String::with_capacity(line.len() * 2);
+        let mut start = 0;
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, ch)) = chars.next() {
+            if ch == '"' && self.quoted {
+                // Doubled quotes are a quote in the field.
+                if chars.peek().map(|&(_, next)| next) == Some('"') {
+                    chars.next();
+                } else {
+                    self.quoted = false;
+                }
+            } else if ch == '"' && i == start {
+                self.quoted = true;
+            } else if ch == separator && !self.quoted {
+                converted.push_str(&self.field_text(start, &line[start..i], convert));
+                converted.push(separator);
+                start = i + separator.len_utf8();
+                self.field += 1;
+            }
+        }
+        converted.push_str(&self.field_text(start, &line[start..], convert));
+        if !self.quoted {
+            self.row += 1;
+        }
+        converted
+    }
+
+    fn field_text<F: FnMut(usize, &str) -> String>(&mut self, start: usize, text: &str,
+        convert: &mut F)
+        -> String
+    {
+        if self.header && self.row == 0 {
+            let name = text.trim().trim_matches('"');
+            if self.names.iter().any(|wanted| wanted == name) {
+                self.selected.get_or_insert_with(Vec::new).push(self.field);
+            }
+            return text.to_string();
+        }
+        let selected = match self.selected {
+            Some(ref selected) => selected.contains(&self.field),
+            None => true,
+        };
+        if selected { convert(start, text) } else { text.to_string() }
+    }
+}
+
+/// The character and length of a code fence at the start of a line.
+fn fence_of(line: &str) -> Option<(char, usize)> {
+    let ch = line.chars().next()?;
+    if ch != '`' && ch != '~' {
+        return None;
+    }
+    let length = line.chars().take_while(|&c| c == ch).count();
+    if length >= 3 { Some((ch, length)) } else { None }
+}
+
+/// Whether a line is like `[label]: https://example.com "Title"`.
+fn is_reference_definition(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with('[') && trimmed.find("]:").is_some()
+}
+
+/// Converts the text between the syntax that `syntax` finds. It is given
+/// the character before and the rest of the line, and says how many bytes
+/// of syntax start it.
+fn convert_outside<F, S>(line: &str, convert: &mut F, syntax: S) -> String
+    where F: FnMut(usize, &str) -> String, S: Fn(Option<char>, &str) -> Option<usize>
+{
+    let mut converted = String::with_capacity(line.len() * 2);
+    let mut prose_start = 0;
+    let mut i = 0;
+    while i < line.len() {
+        match syntax(line[..i].chars().next_back(), &line[i..]) {
+            Some(length) => {
+                converted.push_str(&convert(prose_start, &line[prose_start..i]));
+                converted.push_str(&line[i..i + length]);
+                i += length;
+                prose_start = i;
+            }
+            None => i += line[i..].chars().next().map_or(1, |ch| ch.len_utf8()),
+        }
+    }
+    converted.push_str(&convert(prose_start, &line[prose_start..]));
+    converted
+}
+
+/// Finds Markdown that isn't prose: code spans, link targets, URLs and
+/// tags. URLs only start a word.
+fn markdown_syntax(previous: Option<char>, rest: &str) -> Option<usize> {
+    if rest.starts_with('`') {
+        let ticks = rest.chars().take_while(|&ch| ch == '`').count();
+        let fence = &rest[..ticks];
+        // Only a run of exactly as many backticks closes the span.
+        let mut from = ticks;
+        while let Some(found) = rest[from..].find(fence) {
+            let end = from + found + ticks;
+            if !rest[end..].starts_with('`') {
+                return Some(end);
+            }
+            from = end + rest[end..].chars().take_while(|&ch| ch == '`').count();
+        }
+        return None;
+    }
+    if rest.starts_with("](") {
+        return rest.find(')').map(|end| end + 1);
+    }
+    if rest.starts_with('<') {
+        let looks_like_tag = rest[1..].chars().next()
+            .map_or(false, |ch| ch.is_ascii_alphabetic() || ch == '/' || ch == '!');
+        if looks_like_tag {
+            return rest.find('>').map(|end| end + 1);
+        }
+        return None;
+    }
+    let word_start = previous.map_or(true, |ch| !ch.is_alphanumeric());
+    let url = rest.starts_with("http://") || rest.starts_with("https://")
+        || rest.starts_with("www.");
+    if url && word_start {
+        return Some(rest.find(|ch: char| ch.is_whitespace() || ch == ')').unwrap_or(rest.len()));
+    }
+    None
+}
+
+/// Finds the tags in subtitle text, like `<i>` and `{\an8}`.
+fn subtitle_tag(_: Option<char>, rest: &str) -> Option<usize> {
+    let close = match rest.chars().next() {
+        Some('<') => '>',
+        Some('{') => '}',
+        _ => return None,
+    };
+    rest.find(close).map(|end| end + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_columns, Column, Format, Scanner};
+
+    /// Runs the lines through a scanner that upper-cases the prose.
+    fn scan(format: Format, columns: &[Column], header: bool, lines: &[&str]) -> Vec<String> {
+        let mut scanner = Scanner::new(format, columns, header);
+        lines.iter().map(|line| scanner.line(line, |text| text.to_uppercase())).collect()
+    }
+
+    #[test]
+    fn leaves_front_matter_and_fences() {
+        let lines = ["---", "title: a", "---", "text", "```rust", "let a = b;", "```", "more"];
+        assert_eq!(scan(Format::Markdown, &[], false, &lines),
+            vec!["---", "title: a", "---", "TEXT", "```rust", "let a = b;", "```", "MORE"]);
+    }
+
+    #[test]
+    fn only_closes_a_fence_with_a_long_enough_one() {
+        let lines = ["````", "```", "code", "````", "text"];
+        assert_eq!(scan(Format::Markdown, &[], false, &lines),
+            vec!["````", "```", "code", "````", "TEXT"]);
+    }
+
+    #[test]
+    fn leaves_code_links_and_urls() {
+        let lines = ["a `b` [c](d) <e> www.f.com g"];
+        assert_eq!(scan(Format::Markdown, &[], false, &lines),
+            vec!["A `b` [C](d) <e> www.f.com G"]);
+    }
+
+    #[test]
+    fn finds_the_prose_in_a_line() {
+        let mut scanner = Scanner::new(Format::Markdown, &[], false);
+        assert_eq!(scanner.prose("a `b` c"), vec![(0, 2), (5, 7)]);
+        let mut scanner = Scanner::new(Format::Csv, &[Column::Number(2)], false);
+        assert_eq!(scanner.prose("a,b,c"), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn only_finds_urls_at_the_start_of_a_word() {
+        let lines = ["awww.b ohttp://c"];
+        assert_eq!(scan(Format::Markdown, &[], false, &lines), vec!["AWWW.B OHTTP://C"]);
+    }
+
+    #[test]
+    fn converts_cue_text_only() {
+        let lines = ["WEBVTT", "", "1", "00:00:01.000 --> 00:00:02.000", "<i>a</i> b",
+            "c {\\an8}", "", "d"];
+        assert_eq!(scan(Format::Subtitles, &[], false, &lines), vec!["WEBVTT", "", "1",
+            "00:00:01.000 --> 00:00:02.000", "<i>A</i> B", "C {\\an8}", "", "d"]);
+    }
+
+    #[test]
+    fn follows_quoted_fields_over_lines() {
+        let columns = parse_columns("note").unwrap();
+        let lines = ["id,note", "a,\"b", "c,d\"", "e,f"];
+        assert_eq!(scan(Format::Csv, &columns, false, &lines),
+            vec!["id,note", "a,\"B", "C,D\"", "e,F"]);
+    }
+
+    #[test]
+    fn keeps_doubled_quotes_in_a_field() {
+        let lines = ["\"a \"\"b\"\", c\",d"];
+        assert_eq!(scan(Format::Csv, &[Column::Number(2)], false, &lines),
+            vec!["\"a \"\"b\"\", c\",D"]);
+    }
+}
@@ -0,0 +1,154 @@
+//! A global shortcut that shows and hides the window, grabbed from X11.
+//!
+//! Shortcuts are written like `ctrl+alt+k`, with the key named as an X
+//! keysym (`space`, `F12`, `k`...). Wayland compositors don't let clients
+//! grab keys, so there the desktop's own shortcut settings are needed.
+//!
+//! The shortcut is listened for on a thread of its own, with its own
+//! display connection. `init` has to be called before SDL or anything else
+//! uses Xlib, so that Xlib is safe to use from both threads.
+
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_int, c_uint};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Mutex, Once};
+use std::thread;
+
+use x11::xlib;
+
+use control::Control;
+
+type ErrorHandler = Option<unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent)
+    -> c_int>;
+
+/// The connection the shortcut is grabbed on, whose errors are ours.
+static GRAB_DISPLAY: AtomicPtr<xlib::Display> = AtomicPtr::new(ptr::null_mut());
+/// The handler that was there before ours, which gets every other error.
+static PREVIOUS_HANDLER: Mutex<ErrorHandler> = Mutex::new(None);
+
+/// The modifiers of a shortcut, and the name of its key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortcut {
+    modifiers: c_uint,
+    key: String,
+}
+
+impl Shortcut {
+    pub fn parse(spec: &str) -> Option<Shortcut> {
+        let mut modifiers = 0;
+        let mut key = None;
+        for part in spec.split('+').map(str::trim) {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers |= xlib::ControlMask,
+                "shift" => modifiers |= xlib::ShiftMask,
+                "alt" => modifiers |= xlib::Mod1Mask,
+                "super" | "win" | "gui" => modifiers |= xlib::Mod4Mask,
+                _ if key.is_none() && !part.is_empty() => key = Some(part.to_string()),
+                _ => return None,
+            }
+        }
+        key.map(|key| Shortcut { modifiers: modifiers, key: key })
+    }
+}
+
+/// Makes Xlib safe to use from more than one thread, and puts in the
+/// error handler for grabbing. The handler stays for good, since setting
+/// it while SDL uses Xlib on the main thread would race with it.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe {
+        if xlib::XInitThreads() == 0 {
+            warn!("Xlib can't be used from more than one thread");
+        }
+        let previous = xlib::XSetErrorHandler(Some(on_error));
+        *PREVIOUS_HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = previous;
+    });
+}
+
+/// Starts listening for the shortcut, sending `ToggleWindow` on presses.
+pub fn spawn(shortcut: Shortcut, sender: Sender<Control>) {
+    thread::spawn(move || {
+        if let Err(reason) = listen(&shortcut, &sender) {
+            warn!("Global shortcut '{}' is disabled: {}", shortcut.key, reason);
+        }
+    });
+}
+
+unsafe extern "C" fn on_error(display: *mut xlib::Display, event: *mut xlib::XErrorEvent)
+    -> c_int
+{
+    if display != GRAB_DISPLAY.load(Ordering::SeqCst) {
+        let previous = *PREVIOUS_HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        return match previous {
+            Some(handler) => handler(display, event),
+            None => 0,
+        };
+    }
+    if (*event).error_code == xlib::BadAccess {
+        warn!("The global shortcut is already taken by another program");
+    } else {
+        warn!("X error {} while grabbing the global shortcut", (*event).error_code);
+    }
+    0
+}
+
+fn listen(shortcut: &Shortcut, sender: &Sender<Control>) -> Result<(), String> {
+    let key = CString::new(shortcut.key.as_str())
+        .map_err(|_| String::from("invalid key name"))?;
+    unsafe {
+        let display = xlib::XOpenDisplay(ptr::null());
+        if display.is_null() {
+            return Err(String::from("could not connect to an X11 display"));
+        }
+        let keysym = xlib::XStringToKeysym(key.as_ptr());
+        if keysym == 0 {
+            return Err(format!("unknown key '{}'", shortcut.key));
+        }
+        GRAB_DISPLAY.store(display, Ordering::SeqCst);
+        let keycode = xlib::XKeysymToKeycode(display, keysym) as c_int;
+        let root = xlib::XDefaultRootWindow(display);
+        // Caps Lock and Num Lock would otherwise stop the shortcut working.
+        let locks = [0, xlib::LockMask, xlib::Mod2Mask, xlib::LockMask | xlib::Mod2Mask];
+        for &lock in &locks {
+            xlib::XGrabKey(display, keycode, shortcut.modifiers | lock, root, xlib::False,
+                xlib::GrabModeAsync, xlib::GrabModeAsync);
+        }
+        xlib::XSync(display, xlib::False);
+        xlib::XSelectInput(display, root, xlib::KeyPressMask);
+        info!("Listening for the global shortcut");
+
+        let mut event: xlib::XEvent = mem::zeroed();
+        loop {
+            xlib::XNextEvent(display, &mut event);
+            if event.get_type() == xlib::KeyPress {
+                debug!("Global shortcut pressed");
+                if sender.send(Control::ToggleWindow).is_err() {
+                    break;
+                }
+            }
+        }
+        GRAB_DISPLAY.store(ptr::null_mut(), Ordering::SeqCst);
+        xlib::XCloseDisplay(display);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use x11::xlib;
+
+    use super::Shortcut;
+
+    #[test]
+    fn parses_shortcuts() {
+        let shortcut = Shortcut::parse("ctrl+Alt+k").unwrap();
+        assert_eq!(shortcut.modifiers, xlib::ControlMask | xlib::Mod1Mask);
+        assert_eq!(shortcut.key, "k");
+        assert_eq!(Shortcut::parse("super + F12").unwrap().key, "F12");
+        assert!(Shortcut::parse("ctrl+a+b").is_none());
+        assert!(Shortcut::parse("ctrl+alt").is_none());
+    }
+}
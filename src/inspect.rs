@@ -0,0 +1,85 @@
+//! What a syllable is made of, for the inspector panel.
+
+use normalize;
+use romanize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inspection {
+    pub ch: char,
+    /// The initial, vowel and final, as compatibility jamo, if it is a
+    /// syllable.
+    pub jamo: Option<(char, char, Option<char>)>,
+    /// What each of the jamo is typed as in dero.
+    pub keys: Option<(&'static str, &'static str, Option<String>)>,
+    /// The conjoining jamo that NFD splits the syllable into.
+    pub nfd: String,
+    /// What it was typed as, or converted to in romanize mode.
+    pub source: String,
+}
+
+impl Inspection {
+    pub fn of(ch: char, source: &str) -> Inspection {
+        let jamo = normalize::compatibility_jamo(ch);
+        let keys = romanize::dero_keys(ch);
+        let nfd = normalize::decompose(&ch.to_string());
+        Inspection {
+            ch: ch,
+            jamo: jamo,
+            keys: keys,
+            nfd: nfd,
+            source: source.to_string(),
+        }
+    }
+
+    /// The lines the panel shows: the character, then each of its jamo
+    /// with its key and its code point in NFD, then what it came from.
+    pub fn lines(&self) -> Vec<String> {
+        let first = format!("{}  {}", self.ch, code_points(&self.ch.to_string()));
+        let second = match (self.jamo, &self.keys) {
+            (Some((initial, vowel, last)), &Some((initial_key, vowel_key, ref last_key))) => {
+                let mut jamo = vec![(initial, initial_key), (vowel, vowel_key)];
+                if let (Some(last), &Some(ref last_key)) = (last, last_key) {
+                    jamo.push((last, last_key.as_str()));
+                }
+                jamo.into_iter().zip(self.nfd.chars())
+                    .map(|((ch, key), conjoining)| {
+                        format!("{} \"{}\" {}", ch, key, code_points(&conjoining.to_string()))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("   ")
+            }
+            _ => format!("NFD {}", code_points(&self.nfd)),
+        };
+        vec![first, second, format!("from \"{}\"", self.source)]
+    }
+}
+
+/// Code points written like `U+D55C U+AD6D`.
+pub fn code_points(text: &str) -> String {
+    text.chars()
+        .map(|ch| format!("U+{:04X}", ch as u32))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{code_points, Inspection};
+
+    #[test]
+    fn lists_each_jamo_with_its_key_and_code_point() {
+        let inspection = Inspection::of('닭', "dalg");
+        assert_eq!(inspection.lines(), vec![
+            "닭  U+B2ED".to_string(),
+            "ㄷ \"d\" U+1103   ㅏ \"a\" U+1161   ㄺ \"lg\" U+11B0".to_string(),
+            "from \"dalg\"".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn shows_a_silent_initial_and_other_characters() {
+        assert!(Inspection::of('아', "a").lines()[1].starts_with("ㅇ \"\" U+110B"));
+        assert_eq!(Inspection::of('a', "a").lines()[1], "NFD U+0061");
+        assert_eq!(code_points("한국"), "U+D55C U+AD6D");
+    }
+}
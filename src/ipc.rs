@@ -0,0 +1,500 @@
+//! A control socket, so that editors and scripts can drive the running
+//! window, and `derowin ctl` as its client.
+//!
+//! The socket is `$XDG_RUNTIME_DIR/derowin.sock` (or `derowin.sock` in the
+//! state directory), unless `ipc.socket` says otherwise, and `ipc = off`
+//! turns it off. Each request is a line of JSON with a `cmd`, answered by a
+//! line with `"ok": true` and any results, or `"ok": false` and an `error`:
+//!
+//! ```text
+//! {"cmd": "convert", "text": "hangug"}     -> {"ok": true, "text": "한국"}
+//! {"cmd": "set-mode", "mode": "input"}     -> {"ok": true}
+//! {"cmd": "insert", "text": "eo"}          -> {"ok": true}
+//! {"cmd": "get-buffer"}                    -> {"ok": true, "text": ..., "converted": ...,
+//!                                              "caret": ..., "mode": ...}
+//! {"cmd": "commit"}                        -> {"ok": true, "text": ...}
+//! {"cmd": "show", "mode": "lookup",
+//!  "text": "eo"}                           -> {"ok": true}
+//! {"cmd": "subscribe"}                     -> {"ok": true}, and then
+//!                                             {"event": "commit", "text": ..., "mode": ...}
+//!                                             for every commit
+//! ```
+//!
+//! In `show`, both `mode` and `text` are optional. It is how a second
+//! `derowin` hands its arguments to the one that is running, unless
+//! `single-instance = off` (or `--new-instance`) is set.
+//!
+//! The socket is a Unix domain socket, so there is none on other
+//! platforms: each `derowin` runs on its own, and `derowin ctl` fails.
+
+// Elsewhere, the requests are still answered, but nothing sends them.
+#![cfg_attr(not(unix), allow(dead_code, unused_imports))]
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+pub use serde_json::Map;
+use serde_json::{self, Value};
+
+use config::{self, Config};
+use control::Control;
+use error::DerowinError;
+use session;
+use DeroMode;
+
+/// How long a running derowin has to answer `show` before another one
+/// starts anyway.
+#[cfg(unix)]
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A request that needs the main loop's state.
+#[derive(Debug)]
+pub enum Request {
+    /// Converts text with the main loop's rules, leaving the buffer be.
+    Convert(String),
+    Insert(String),
+    /// Text handed over by `show`, which waits for the restore prompt to be
+    /// answered, like the text on the command line.
+    Forward(String),
+    GetBuffer,
+    Commit,
+    /// Sends every commit to the sender, until it hangs up.
+    Subscribe(Sender<Value>),
+}
+
+/// The fields of a successful reply, or why it failed.
+pub type Reply = Result<Map<String, Value>, String>;
+
+pub fn socket_path(config: &Config) -> Option<PathBuf> {
+    if let Some(path) = config.get("ipc.socket") {
+        return Some(PathBuf::from(path));
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("derowin.sock")),
+        _ => session::state_dir().map(|dir| dir.join("derowin.sock")),
+    }
+}
+
+/// Starts listening on the control socket, unless it is turned off.
+/// Returns the socket's path, if it is listening.
+#[cfg(unix)]
+pub fn start(config: &Config, sender: &Sender<Control>) -> Result<Option<PathBuf>, DerowinError> {
+    if !config.parse_with("ipc", config::parse_bool)?.unwrap_or(true) {
+        return Ok(None);
+    }
+    let path = match socket_path(config) {
+        Some(path) => path,
+        None => {
+            warn!("No place for the control socket; set ipc.socket");
+            return Ok(None);
+        }
+    };
+    match listen(&path) {
+        Ok(listener) => {
+            info!("Listening on {}", path.display());
+            let sender = sender.clone();
+            thread::spawn(move || accept(listener, sender));
+            Ok(Some(path))
+        }
+        Err(e) => {
+            warn!("Could not listen on {}: {}", path.display(), e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn start(config: &Config, _: &Sender<Control>) -> Result<Option<PathBuf>, DerowinError> {
+    if config.parse_with("ipc", config::parse_bool)?.unwrap_or(true) {
+        info!("There is no control socket on this platform");
+    }
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn listen(path: &Path) -> io::Result<UnixListener> {
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                "another derowin is listening there"));
+        }
+        // Left behind by a derowin that didn't quit cleanly.
+        fs::remove_file(path)?;
+    }
+    // Bound in a directory only we can enter, so that nobody else can
+    // connect before the socket's own permissions are set, and then moved
+    // into place.
+    let private = dir.join(format!(".derowin-{}", process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("derowin.sock");
+    let listener = UnixListener::bind(&bound)
+        .and_then(|listener| {
+            fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+            fs::rename(&bound, path)?;
+            Ok(listener)
+        });
+    if listener.is_err() {
+        let _ = fs::remove_file(&bound);
+    }
+    if let Err(e) = fs::remove_dir(&private) {
+        debug!("Could not remove {}: {}", private.display(), e);
+    }
+    listener
+}
+
+/// Removes the socket when quitting.
+pub fn stop(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        debug!("Could not remove {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(unix)]
+fn accept(listener: UnixListener, sender: Sender<Control>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, sender) {
+                        debug!("Control connection ended: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Could not accept a control connection: {}", e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn serve(stream: UnixStream, sender: Sender<Control>) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (reply, events) = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle(&request, &sender),
+            Err(e) => (Err(format!("invalid JSON: {}", e)), None),
+        };
+        write_line(&mut writer, &reply_value(reply))?;
+        if let Some(events) = events {
+            // Subscribers get nothing but events from now on.
+            for event in events {
+                write_line(&mut writer, &event)?;
+            }
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Answers a request, along with the events to send on if it subscribed.
+fn handle(request: &Value, sender: &Sender<Control>) -> (Reply, Option<mpsc::Receiver<Value>>) {
+    let text = || request.get("text").and_then(Value::as_str).map(String::from)
+        .ok_or_else(|| String::from("missing 'text'"));
+    let main_loop = |request: Request| -> Reply {
+        let (reply_sender, reply) = mpsc::channel();
+        sender.send(Control::Ipc(request, reply_sender))
+            .map_err(|_| String::from("derowin is quitting"))?;
+        reply.recv().unwrap_or_else(|_| Err(String::from("derowin is quitting")))
+    };
+    let reply = match request.get("cmd").and_then(Value::as_str) {
+        Some("convert") => text().and_then(|text| main_loop(Request::Convert(text))),
+        Some("set-mode") => {
+            match request.get("mode").and_then(Value::as_str).and_then(DeroMode::from_name) {
+                Some(mode) => sender.send(Control::SetMode(mode))
+                    .map(|_| Map::new())
+                    .map_err(|_| String::from("derowin is quitting")),
+                None => Err(String::from("'mode' must be default, input, lookup or romanize")),
+            }
+        }
+        Some("insert") => text().and_then(|text| main_loop(Request::Insert(text))),
+        Some("show") => show(request, sender, &main_loop),
+        Some("get-buffer") => main_loop(Request::GetBuffer),
+        Some("commit") => main_loop(Request::Commit),
+        Some("subscribe") => {
+            let (events_sender, events) = mpsc::channel();
+            let reply = main_loop(Request::Subscribe(events_sender));
+            let events = if reply.is_ok() { Some(events) } else { None };
+            return (reply, events);
+        }
+        Some(cmd) => Err(format!("unknown command '{}'", cmd)),
+        None => Err(String::from("missing 'cmd'")),
+    };
+    (reply, None)
+}
+
+fn show<F>(request: &Value, sender: &Sender<Control>, main_loop: &F) -> Reply
+    where F: Fn(Request) -> Reply
+{
+    let quitting = |_| String::from("derowin is quitting");
+    if let Some(name) = request.get("mode").and_then(Value::as_str) {
+        let mode = DeroMode::from_name(name)
+            .ok_or_else(|| String::from("'mode' must be default, input, lookup or romanize"))?;
+        sender.send(Control::SetMode(mode)).map_err(&quitting)?;
+    }
+    let forwarded = match request.get("text").and_then(Value::as_str) {
+        Some(text) => main_loop(Request::Forward(text.to_string())),
+        None => Ok(Map::new()),
+    };
+    // The window comes up even if the text couldn't be given to it.
+    sender.send(Control::ShowWindow).map_err(&quitting)?;
+    forwarded
+}
+
+/// Hands the mode and text to a running derowin and brings it to the
+/// front, returning whether there was one. One that doesn't answer in
+/// time counts as none.
+#[cfg(unix)]
+pub fn forward(config: &Config, mode: Option<DeroMode>, text: Option<&str>)
+    -> Result<bool, DerowinError>
+{
+    let path = match socket_path(config) {
+        Some(ref path) if path.exists() => path.clone(),
+        _ => return Ok(false),
+    };
+    let mut stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            debug!("No running derowin at {}: {}", path.display(), e);
+            return Ok(false);
+        }
+    };
+    let mut request = Map::new();
+    request.insert(String::from("cmd"), Value::from("show"));
+    if let Some(mode) = mode {
+        request.insert(String::from("mode"), Value::from(mode.name()));
+    }
+    if let Some(text) = text {
+        request.insert(String::from("text"), Value::from(text));
+    }
+    let ipc_err = |e: io::Error| DerowinError::Ipc(format!("{}: {}", path.display(), e));
+    stream.set_read_timeout(Some(FORWARD_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(FORWARD_TIMEOUT)))
+        .map_err(&ipc_err)?;
+    let reply = write_line(&mut stream, &Value::Object(request))
+        .and_then(|_| read_reply(BufReader::new(stream).lines().next()));
+    match reply {
+        Ok(reply) => check_reply(&reply).map(|_| true),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+            || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            warn!("The derowin at {} isn't answering; starting another", path.display());
+            Ok(false)
+        }
+        Err(e) => Err(ipc_err(e)),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn forward(_: &Config, _: Option<DeroMode>, _: Option<&str>) -> Result<bool, DerowinError> {
+    Ok(false)
+}
+
+fn check_reply(reply: &Value) -> Result<(), DerowinError> {
+    if reply.get("ok").and_then(Value::as_bool) == Some(true) {
+        Ok(())
+    } else {
+        let error = reply.get("error").and_then(Value::as_str).unwrap_or("unknown error");
+        Err(DerowinError::Ipc(error.to_string()))
+    }
+}
+
+fn reply_value(reply: Reply) -> Value {
+    let mut fields = Map::new();
+    match reply {
+        Ok(results) => {
+            fields.insert(String::from("ok"), Value::Bool(true));
+            fields.extend(results);
+        }
+        Err(error) => {
+            fields.insert(String::from("ok"), Value::Bool(false));
+            fields.insert(String::from("error"), Value::from(error));
+        }
+    }
+    Value::Object(fields)
+}
+
+fn write_line<W: Write>(writer: &mut W, value: &Value) -> io::Result<()> {
+    let mut line = value.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+/// The event sent to subscribers for a commit.
+pub fn commit_event(mode: DeroMode, text: &str) -> Value {
+    let mut fields = Map::new();
+    fields.insert(String::from("event"), Value::from("commit"));
+    fields.insert(String::from("text"), Value::from(text));
+    fields.insert(String::from("mode"), Value::from(mode.name()));
+    Value::Object(fields)
+}
+
+/// Sends an event to the subscribers, forgetting those that hung up.
+pub fn notify(subscribers: &mut Vec<Sender<Value>>, event: Value) {
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+/// Runs `derowin ctl`, printing the results.
+#[cfg(unix)]
+pub fn client(config: &Config, args: &[String]) -> Result<(), DerowinError> {
+    let command = args.first().map(|arg| arg.as_str()).unwrap_or("");
+    let rest = &args[args.len().min(1)..];
+    let mut request = Map::new();
+    let cmd = match command {
+        "convert" | "insert" => {
+            request.insert(String::from("text"), Value::from(text_argument(rest)?));
+            command
+        }
+        "mode" => {
+            let mode = rest.first()
+                .ok_or_else(|| DerowinError::Usage(String::from("Missing mode for 'ctl mode'")))?;
+            request.insert(String::from("mode"), Value::from(mode.as_str()));
+            "set-mode"
+        }
+        "show" => {
+            if let Some(mode) = rest.first() {
+                request.insert(String::from("mode"), Value::from(mode.as_str()));
+            }
+            command
+        }
+        "buffer" => "get-buffer",
+        "commit" | "subscribe" => command,
+        _ => return Err(DerowinError::Usage(format!("Unknown ctl command '{}'", command))),
+    };
+    request.insert(String::from("cmd"), Value::from(cmd));
+
+    let path = socket_path(config)
+        .ok_or_else(|| DerowinError::Ipc(String::from("no socket path; set ipc.socket")))?;
+    let ipc_err = |e: io::Error| DerowinError::Ipc(format!("{}: {}", path.display(), e));
+    let mut stream = UnixStream::connect(&path).map_err(&ipc_err)?;
+    write_line(&mut stream, &Value::Object(request)).map_err(&ipc_err)?;
+    let mut lines = BufReader::new(stream).lines();
+    let reply = read_reply(lines.next()).map_err(&ipc_err)?;
+    check_reply(&reply)?;
+    match cmd {
+        "convert" | "commit" => print_field(&reply, "text"),
+        "get-buffer" => print_field(&reply, "converted"),
+        "subscribe" => loop {
+            let event = read_reply(lines.next()).map_err(&ipc_err)?;
+            print_field(&event, "text");
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn client(_: &Config, _: &[String]) -> Result<(), DerowinError> {
+    Err(DerowinError::Ipc(String::from("there is no control socket on this platform")))
+}
+
+/// The text for `convert` and `insert`: the arguments, or else stdin.
+fn text_argument(args: &[String]) -> Result<String, DerowinError> {
+    if !args.is_empty() {
+        return Ok(args.join(" "));
+    }
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text)
+        .map_err(|e| DerowinError::Ipc(format!("could not read stdin: {}", e)))?;
+    Ok(text)
+}
+
+fn read_reply(line: Option<io::Result<String>>) -> io::Result<Value> {
+    let line = line.unwrap_or_else(|| {
+        Err(io::Error::new(io::ErrorKind::UnexpectedEof, "derowin hung up"))
+    })?;
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn print_field(reply: &Value, field: &str) {
+    if let Some(text) = reply.get(field).and_then(Value::as_str) {
+        println!("{}", text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use serde_json;
+
+    use control::Control;
+    use super::{show, Request};
+
+    #[test]
+    fn show_brings_the_window_up_even_if_the_text_is_refused() {
+        let (sender, controls) = mpsc::channel();
+        let request = serde_json::from_str(r#"{"cmd": "show", "text": "eo"}"#).unwrap();
+        let reply = show(&request, &sender, &|request| match request {
+            Request::Forward(ref text) if text == "eo" => Err(String::from("refused")),
+            _ => panic!("expected the text to be forwarded"),
+        });
+        assert_eq!(reply, Err(String::from("refused")));
+        match controls.try_recv() {
+            Ok(Control::ShowWindow) => {}
+            other => panic!("expected ShowWindow, got {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listens_on_a_socket_only_we_can_use() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+        use std::{env, fs, process};
+
+        use super::listen;
+
+        let dir = env::temp_dir().join(format!("derowin-ipc-test-{}", process::id()));
+        let path = dir.join("derowin.sock");
+        let listener = listen(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Nothing is left of the directory it was bound in.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(UnixStream::connect(&path).is_ok());
+        assert!(listen(&path).is_err());
+        drop(listener);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn starts_anyway_when_the_running_one_does_not_answer() {
+        use std::os::unix::net::UnixListener;
+        use std::{env, fs, process};
+
+        use config::Config;
+
+        use super::forward;
+
+        let path = env::temp_dir().join(format!("derowin-forward-test-{}.sock", process::id()));
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut config = Config::default();
+        config.set("ipc.socket", &path.display().to_string());
+        let forwarded = forward(&config, None, Some("eo"));
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+        assert!(!forwarded.unwrap());
+    }
+}
@@ -0,0 +1,74 @@
+//! The parts of derowin that don't need a window: the buffer, conversion,
+//! and the keys that commit it. They are shared by the SDL app and the
+//! input method engine (`derowin-ibus`).
+
+extern crate dero;
+#[macro_use]
+extern crate log;
+
+pub mod annotate;
+pub mod compose;
+pub mod config;
+pub mod editor;
+pub mod error;
+pub mod escape;
+pub mod inspect;
+pub mod logging;
+pub mod mapping;
+pub mod normalize;
+pub mod romanize;
+pub mod rules;
+
+pub const WINDOW_TITLE: &'static str = "Dero";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeroMode {
+    Default,
+    Lookup,
+    Input,
+    /// Hangul to romanization, the other way round.
+    Romanize,
+}
+
+impl DeroMode {
+    pub const ALL: [DeroMode; 4] = [
+        DeroMode::Default, DeroMode::Input, DeroMode::Lookup, DeroMode::Romanize,
+    ];
+
+    /// The name used for the mode on the command line and in session files.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DeroMode::Default => "default",
+            DeroMode::Lookup => "lookup",
+            DeroMode::Input => "input",
+            DeroMode::Romanize => "romanize",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DeroMode> {
+        match name {
+            "default" => Some(DeroMode::Default),
+            "lookup" => Some(DeroMode::Lookup),
+            "input" => Some(DeroMode::Input),
+            "romanize" => Some(DeroMode::Romanize),
+            _ => None,
+        }
+    }
+
+    /// The name shown for the mode in menus.
+    pub fn label(&self) -> &'static str {
+        match *self {
+            DeroMode::Default => "Default",
+            DeroMode::Lookup => "Look-up",
+            DeroMode::Input => "Input",
+            DeroMode::Romanize => "Romanize",
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match *self {
+            DeroMode::Default => WINDOW_TITLE.to_string(),
+            _ => format!("{} - {}", WINDOW_TITLE, self.label()),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Logging to stderr and, optionally, to a log file that is rotated when
+//! it grows too large.
+//!
+//! The level comes from `--verbose` (`-v`, repeatable) when given, and
+//! otherwise from the `DEROWIN_LOG` environment variable.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{self, LevelFilter, Log, Metadata, Record};
+
+use error::DerowinError;
+
+/// The size in bytes at which the log file is rotated.
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// How many rotated log files are kept (`derowin.log.1` and so on).
+const KEPT_LOGS: usize = 3;
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Warn;
+
+static LOG_TEXT: AtomicBool = AtomicBool::new(false);
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> io::Result<LogFile> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { path: path.to_path_buf(), file: file, size: size })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size + line.len() as u64 > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        for n in (1..KEPT_LOGS).rev() {
+            let from = numbered(n);
+            if from.exists() {
+                fs::rename(&from, numbered(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, numbered(1))?;
+        *self = LogFile::open(&self.path)?;
+        Ok(())
+    }
+}
+
+struct Logger {
+    level: LevelFilter,
+    file: Option<Mutex<LogFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        eprintln!("derowin: [{}] {}", record.level(), record.args());
+        if let Some(ref file) = self.file {
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let line = format!("{} [{}] {}: {}\n",
+                secs, record.level(), record.target(), record.args());
+            if let Ok(mut file) = file.lock() {
+                // There's nowhere left to report this.
+                let _ = file.write_line(&line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+/// Sets up the global logger. `verbosity` is the number of `-v` flags.
+pub fn init(verbosity: usize, log_file: Option<&Path>, log_text: bool)
+    -> Result<(), DerowinError>
+{
+    let level = if verbosity > 0 {
+        level_from_verbosity(verbosity)
+    } else {
+        env::var("DEROWIN_LOG").ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_LEVEL)
+    };
+    let file = match log_file {
+        Some(path) => {
+            let file = LogFile::open(path)
+                .map_err(|e| DerowinError::LogFile(path.display().to_string(), e))?;
+            Some(Mutex::new(file))
+        }
+        None => None,
+    };
+    LOG_TEXT.store(log_text, Ordering::Relaxed);
+    // Only fails if a logger was already set, which is harmless.
+    let _ = log::set_boxed_logger(Box::new(Logger { level: level, file: file }));
+    log::set_max_level(level);
+    Ok(())
+}
+
+fn level_from_verbosity(verbosity: usize) -> LevelFilter {
+    match verbosity {
+        0 => DEFAULT_LEVEL,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Describes user text for the log. The text itself is only shown when
+/// `--log-text` is given, since it may be private.
+pub fn redact(text: &str) -> String {
+    if LOG_TEXT.load(Ordering::Relaxed) {
+        format!("{:?}", text)
+    } else {
+        format!("<{} chars>", text.chars().count())
+    }
+}
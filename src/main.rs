@@ -1,32 +1,95 @@
-extern crate dero;
+#[cfg(all(test, target_os = "linux"))]
+extern crate dbus;
+extern crate derowin;
+extern crate encoding_rs;
+#[cfg(target_os = "linux")]
+extern crate ksni;
+#[cfg(target_os = "linux")]
+extern crate libc;
+#[macro_use]
+extern crate log;
 extern crate rsdl2;
 extern crate rsdl2_font;
+extern crate serde_json;
+#[cfg(target_os = "linux")]
+extern crate x11;
 
 use rsdl2_font::rusttype;
-use rusttype::{FontCollection, Scale};
+use rusttype::{Font, FontCollection};
 use std::thread;
-use std::time::Duration;
-use rsdl2::{Keycode, Keysym, keymod};
-use std::process::{Command, Stdio};
-use std::io::{Write, Read};
-use dero::deromanize_escaped;
+use std::time::{Duration, Instant};
+use rsdl2::{Keycode, Keysym, Rect, Renderer, Window, keymod};
+use rsdl2::events::MouseButton;
+use std::process;
+use std::io::{self, Read};
 use std::fs::File;
 use std::env;
+use std::sync::mpsc;
+use std::path::PathBuf;
 
-const TEXT_POS: (i32, i32) = (10, 10);
-const WINDOW_SIZE: (i32, i32) = (300, 40);
-const MAX_FPS: u32 = 60;
-const WINDOW_TITLE: &'static str = "Dero";
-//const KOREAN_FONT_PATH: &'static str = "/Library/Fonts/NanumGothic.ttc";
-const KOREAN_FONT_PATH: &'static str = "/Library/Fonts/NanumMyeongjo.ttc";
-static FONT_POINT_SIZE: f32 = 18.0;
+mod batch;
+mod clipboard;
+mod control;
+mod desktop;
+mod format;
+#[cfg(target_os = "linux")]
+mod hotkey;
+mod ipc;
+mod menu;
+mod options;
+mod output;
+mod render;
+mod session;
+mod status;
+mod theme;
+mod transform;
+#[cfg(target_os = "linux")]
+mod tray;
+mod typing;
+mod window;
 
-#[derive(Debug, Clone, Copy)]
-pub enum DeroMode {
-    Default,
-    Lookup,
-    Input,
-}
+pub use derowin::{DeroMode, WINDOW_TITLE};
+use derowin::{annotate, compose, config, editor, error, escape, inspect, logging, mapping,
+    normalize, romanize, rules};
+
+use clipboard::Backend;
+use config::Config;
+use compose::{Action, Converter, Effect};
+use control::Control;
+use desktop::Desktop;
+use editor::Editor;
+use ipc::Request;
+use error::DerowinError;
+use escape::Escapes;
+use inspect::Inspection;
+use logging::redact;
+use mapping::Mapping;
+use menu::{ContextMenu, MenuItem};
+use options::Options;
+use romanize::Style;
+use rules::{RuleFile, Rules};
+use output::{Outputs, Sink};
+use render::{BufferView, Metrics};
+use session::Journal;
+use status::StatusBar;
+use theme::Theme;
+use transform::Transforms;
+use window::{Geometry, Layout, Split, WindowOptions};
+
+const WINDOW_SIZE: (i32, i32) = (300, 64);
+const PALETTE_MIN_WIDTH: i32 = 120;
+const MAX_FPS: u32 = 60;
+/// How many lines the inspector panel shows.
+const INSPECTOR_LINES: i32 = 3;
+/// Fonts with Hangul in them, tried in order.
+const KOREAN_FONT_PATHS: &'static [&'static str] = &[
+    //"/Library/Fonts/NanumGothic.ttc",
+    "/Library/Fonts/NanumMyeongjo.ttc",
+    "/usr/share/fonts/truetype/nanum/NanumMyeongjo.ttf",
+    "/usr/share/fonts/naver-nanum/NanumMyeongjo.ttf",
+    "/usr/share/fonts/opentype/noto/NotoSerifCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSerifCJK-Regular.ttc",
+];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Keymask {
@@ -93,6 +156,37 @@ impl Keymask {
         self
     }
     
+    /// Parses a key with modifiers, like `shift+space` or `ctrl+alt+l`.
+    pub fn parse(spec: &str) -> Option<Keymask> {
+        let mut key = None;
+        let (mut ctrl, mut gui, mut shift, mut alt) = (false, false, false, false);
+        for part in spec.split('+').map(str::trim) {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => ctrl = true,
+                "shift" => shift = true,
+                "alt" | "option" => alt = true,
+                "super" | "win" | "gui" | "cmd" => gui = true,
+                name if key.is_none() => key = Some(keycode_from_name(name)?),
+                _ => return None,
+            }
+        }
+        key.map(|key| Keymask { key: key, ctrl: ctrl, gui: gui, shift: shift, alt: alt })
+    }
+
+    /// The text that typing the keys also inputs, if any.
+    pub fn text(&self) -> Option<String> {
+        if self.ctrl || self.gui || self.alt {
+            return None;
+        }
+        match self.key {
+            Keycode::Space => Some(String::from(" ")),
+            key => LETTER_KEYS.iter().position(|&letter| letter == key).map(|i| {
+                let letter = (b'a' + i as u8) as char;
+                if self.shift { letter.to_ascii_uppercase() } else { letter }.to_string()
+            }),
+        }
+    }
+
     #[inline]
     pub fn matches(&self, keysym: Keysym) -> bool {
         keysym.keycode  == self.key
@@ -103,209 +197,1179 @@ impl Keymask {
     }
 }
 
+const LETTER_KEYS: [Keycode; 26] = [
+    Keycode::A, Keycode::B, Keycode::C, Keycode::D, Keycode::E, Keycode::F, Keycode::G,
+    Keycode::H, Keycode::I, Keycode::J, Keycode::K, Keycode::L, Keycode::M, Keycode::N,
+    Keycode::O, Keycode::P, Keycode::Q, Keycode::R, Keycode::S, Keycode::T, Keycode::U,
+    Keycode::V, Keycode::W, Keycode::X, Keycode::Y, Keycode::Z,
+];
+
+fn keycode_from_name(name: &str) -> Option<Keycode> {
+    match name {
+        "space" => Some(Keycode::Space),
+        "tab" => Some(Keycode::Tab),
+        _ if name.len() == 1 => {
+            let letter = name.as_bytes()[0];
+            if letter.is_ascii_lowercase() {
+                Some(LETTER_KEYS[(letter - b'a') as usize])
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
 pub fn main() {
-    let args = env::args().skip(1).collect::<Vec<_>>();
+    if let Err(err) = run() {
+        eprintln!("derowin: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn run() -> Result<(), DerowinError> {
+    let options = Options::parse(env::args().skip(1))?;
+    if options.help {
+        println!("{}", options::USAGE);
+        return Ok(());
+    }
+    logging::init(options.verbosity, options.log_file.as_ref().map(|p| p.as_path()),
+        options.log_text)?;
+    let mut config = Config::load(options.config.as_ref().map(|p| p.as_path()))?;
+    for &(key, ref value) in &options.settings {
+        config.set(key, value);
+    }
+    if let Some(ref args) = options.ctl {
+        return ipc::client(&config, args);
+    }
+    let mut converter = Converter::new();
+    converter.escapes = Escapes::from_config(&config)?;
+    let mut rule_file = RuleFile::from_config(&config)?;
+    if let Some(ref args) = options.rules {
+        return run_rules(&converter, rule_file.as_ref(), args);
+    }
+    if let Some(ref mut rule_file) = rule_file {
+        if let Some(rules) = rule_file.reload() {
+            converter.rules = rules?;
+        }
+    }
+    if let Some(ref args) = options.convert {
+        return batch::run(&config, &converter, args);
+    }
+    if config.parse_with("single-instance", config::parse_bool)?.unwrap_or(true)
+        && ipc::forward(&config, options.mode, options.text.as_ref().map(|t| t.as_str()))?
+    {
+        info!("Handed over to the running derowin");
+        return Ok(());
+    }
+    let window_options = WindowOptions::from_config(&config)?;
     
-    let context = rsdl2::init().everything().finish().expect("init failed");
-    let mut event_context = context.events().expect("Event subsystem not initialized");
-    let video_context = context.video().expect("Video subsystem not initialized");
-    let window = video_context.build_window()
-        .title(WINDOW_TITLE)
-        .size(WINDOW_SIZE.0, WINDOW_SIZE.1)
-        .center(true, true)
-        .resizable()
-        .finish()
-        .expect("Could not create window");
-    let renderer = window.build_renderer().finish().expect("Could not build renderer");
+    // Before SDL opens its own display connection.
+    #[cfg(target_os = "linux")]
+    hotkey::init();
+    let context = rsdl2::init().everything().finish()
+        .map_err(|e| DerowinError::Sdl(format!("{:?}", e)))?;
+    let mut event_context = context.events()
+        .map_err(|e| DerowinError::Sdl(format!("event subsystem: {:?}", e)))?;
+    let video_context = context.video()
+        .map_err(|e| DerowinError::Sdl(format!("video subsystem: {:?}", e)))?;
+    let palette = window_options.layout == Layout::Palette;
+    let geometry = if window_options.remember_geometry { Geometry::load() } else { None };
+    let mut builder = video_context.build_window().title(WINDOW_TITLE);
+    builder = match geometry {
+        // The palette sizes itself, so only its position is kept.
+        Some(geometry) if palette => builder
+            .size(WINDOW_SIZE.0, WINDOW_SIZE.1)
+            .position(geometry.x, geometry.y),
+        Some(geometry) => builder
+            .size(geometry.width, geometry.height)
+            .position(geometry.x, geometry.y),
+        None => builder
+            .size(WINDOW_SIZE.0, WINDOW_SIZE.1)
+            .center(true, true),
+    };
+    if !palette {
+        builder = builder.resizable();
+    }
+    if window_options.always_on_top {
+        builder = builder.always_on_top();
+    }
+    if window_options.borderless {
+        builder = builder.borderless();
+    }
+    let window = builder.finish()
+        .map_err(|e| DerowinError::Window(format!("{:?}", e)))?;
+    if let Some(opacity) = window_options.opacity {
+        if let Err(e) = window.set_opacity(opacity) {
+            warn!("Could not set the window opacity: {:?}", e);
+        }
+    }
+    let renderer = window.build_renderer().finish()
+        .map_err(|e| DerowinError::Window(format!("renderer: {:?}", e)))?;
     //renderer.set_blend_mode(BlendMode::Blend);
     
-    let mut file = File::open(KOREAN_FONT_PATH).expect("Could not open font file");
-    let mut fontbuf = Vec::new();
-    file.read_to_end(&mut fontbuf).expect("Could not read file");
-    let collection = FontCollection::from_bytes(fontbuf);
-    let font = collection.font_at(0).expect("No font at index 0");
+    let font = load_font(KOREAN_FONT_PATHS)?;
+    let clipboard = Backend::detect();
+    let mut outputs = Outputs::from_config(&config, clipboard)?;
+    let transforms = Transforms::from_config(&config)?;
+    let theme = Theme::from_config(&config)?;
+    let style = config.parse_with("romanize.style", Style::from_name)?.unwrap_or(Style::Dero);
+    // Dropped files are loaded into the buffer, or converted next to
+    // themselves.
+    let convert_drops = config.parse_with("drop.files", |value| match value {
+        "load" => Some(false),
+        "convert" => Some(true),
+        _ => None,
+    })?.unwrap_or(false);
+    let batch = batch::Batch::from_config(&config)?;
+    let mut conversions = batch::Queue::new(batch.clone());
     
-    let clear_color = (255, 255, 255);
-    let mut input = String::new();
-    let mut mode = match args.get(0).map(|s| s.as_str()) {
-        Some("input") => DeroMode::Input,
-        Some("lookup") => DeroMode::Lookup,
-        _ => DeroMode::Default,
-    };
-    match mode {
-        DeroMode::Input => window.set_title(&format!("{} - Input", WINDOW_TITLE)),
-        DeroMode::Lookup => window.set_title(&format!("{} - Look-up", WINDOW_TITLE)),
-        DeroMode::Default => {}
+    let mut editor = Editor::new();
+    let mut mode = options.mode.unwrap_or(DeroMode::Default);
+    info!("Starting in {} mode", mode.name());
+    window.set_title(&mode.title());
+    let (control_sender, controls) = mpsc::channel();
+    let desktop = Desktop::start(&config, &control_sender, mode)?;
+    let socket = ipc::start(&config, &control_sender)?;
+    let mut subscribers = Vec::new();
+    let mut visible = true;
+    let mut quit = false;
+    let mut journal = Journal::new();
+    let mut restore_offer = journal.load();
+    // Text from the command line, or from a second derowin, waits until the
+    // restore offer is answered.
+    let mut pending_text = options.text.clone();
+    if restore_offer.is_some() {
+        window.set_title(&format!("{} - Restore previous session?", WINDOW_TITLE));
+    } else if let Some(text) = pending_text.take() {
+        editor.insert(&text);
     }
+    // Runtime errors are shown in the window until dismissed.
+    let mut banner: Option<DerowinError> = None;
+    let mut status = StatusBar::new();
     let mut dirty = true;
+    let mut caret_on = true;
+    let mut blink_start = Instant::now();
+    let mut rules_checked = Instant::now();
+    let mut menu: Option<ContextMenu> = None;
+    let mut dragging = false;
+    // Lines scrolled out of view, and whether to keep the caret in view.
+    let mut scroll = 0;
+    let mut follow_caret = true;
+    // Where the text was last drawn.
+    let mut panes = Panes { source: None, output: theme.padding };
+    let mut split = window_options.split;
+    let mut inspecting = false;
+    let mut annotations = window_options.annotations;
+    // How the buffer maps onto what is shown, made again when it changes.
+    let mut mappings = mapping::Cache::new();
+    // What a system input method is composing, which isn't in the buffer
+    // until it's done.
+    let mut preedit = String::new();
+    let mut input_rect = None;
+    // Text input to drop, since the key that typed it was a shortcut.
+    let mut swallowed: Option<String> = None;
+    // The mode, and whether literal input was on, that the title shows.
+    let mut title_shown = None;
     
     let m_input = Keymask::new(Keycode::I).shortcut().shift();
     let m_lookup = Keymask::new(Keycode::L).shortcut().shift();
+    let m_romanize = Keymask::new(Keycode::R).shortcut().shift();
+    let m_split = Keymask::new(Keycode::S).shortcut().shift();
+    let m_inspect = Keymask::new(Keycode::J).shortcut().shift();
+    let m_annotate = Keymask::new(Keycode::A).shortcut().shift();
+    // Switches between romanization and literal Latin input.
+    let m_latin = config.parse_with("keys.latin", Keymask::parse)?
+        .unwrap_or(Keymask::new(Keycode::Space).shift());
     let m_enter = Keymask::new(Keycode::Return);
     let m_newline = Keymask::new(Keycode::Return).shift();
+    let m_escape = Keymask::new(Keycode::Escape);
     let m_backspace = Keymask::new(Keycode::Backspace);
+    let m_delete = Keymask::new(Keycode::Delete);
+    let m_left = Keymask::new(Keycode::Left);
+    let m_right = Keymask::new(Keycode::Right);
+    let m_home = Keymask::new(Keycode::Home);
+    let m_end = Keymask::new(Keycode::End);
     let m_paste = Keymask::new(Keycode::V).shortcut();
     let m_clear = Keymask::new(Keycode::A).shortcut();
+    let m_undo = Keymask::new(Keycode::Z).shortcut();
+    let m_redo = Keymask::new(Keycode::Z).shortcut().shift();
+    let edit_keys = [
+        (m_backspace, Action::Backspace),
+        (m_delete, Action::Delete),
+        (m_left, Action::Left),
+        (m_right, Action::Right),
+        (m_home, Action::Home),
+        (m_end, Action::End),
+        (m_undo, Action::Undo),
+        (m_redo, Action::Redo),
+        (m_enter, Action::Enter),
+        (m_newline, Action::Newline),
+        (m_clear, Action::Commit),
+    ];
 
     'main: loop {
         use rsdl2::events::EventKind::*;
         for event in event_context.events() {
             match event.kind {
                 Quit => {
-                    break 'main;
+                    quit = true;
+                }
+                // Nothing is typed until the restore offer is answered.
+                TextInput(_) | TextEditing { .. } if restore_offer.is_some() => {}
+                TextInput(ref text) if swallowed.as_ref() == Some(text) => {
+                    swallowed = None;
                 }
                 TextInput(ref text) => {
-                    input.push_str(text);
+                    preedit.clear();
+                    editor.insert(text);
+                    follow_caret = true;
+                    dirty = true;
+                }
+                DropText(_) | DropFile(_) if restore_offer.is_some() => {}
+                DropText(ref text) => {
+                    editor.insert(text);
+                    follow_caret = true;
+                    dirty = true;
+                }
+                DropFile(ref path) if convert_drops => {
+                    conversions.push(PathBuf::from(path));
+                    status.show(conversions.progress());
+                    dirty = true;
+                }
+                DropFile(ref path) => {
+                    let loaded = batch.load(&converter.escapes, path.as_ref());
+                    if let Some(text) = report(&mut banner, loaded) {
+                        editor.insert(&text);
+                        follow_caret = true;
+                    }
                     dirty = true;
                 }
+                TextEditing { ref text, .. } => {
+                    if *text != preedit {
+                        preedit = text.clone();
+                        follow_caret = true;
+                        dirty = true;
+                    }
+                }
                 // Drawing a little too much here, since window events
                 // aren't too well-supported, but I'll want redraws on
                 // resize.
                 Window(ref _window) => {
                     dirty = true;
                 }
+                KeyDown(sym) | KeyRepeat(sym) if restore_offer.is_some() => {
+                    if m_enter.matches(sym) {
+                        let session = restore_offer.take().unwrap();
+                        info!("Restoring the previous session in {} mode", session.mode.name());
+                        editor = session.editor;
+                        mode = session.mode;
+                    } else if m_escape.matches(sym) {
+                        info!("Discarding the previous session");
+                        restore_offer = None;
+                    } else {
+                        continue;
+                    }
+                    if let Some(text) = pending_text.take() {
+                        editor.insert(&text);
+                    }
+                    desktop.set_mode(mode);
+                    journal.save(mode, &editor);
+                    dirty = true;
+                }
+                // The input method has the keys while it's composing.
+                KeyDown(_) | KeyRepeat(_) if !preedit.is_empty() => {}
                 KeyDown(sym) | KeyRepeat(sym) => {
-                    if m_backspace.matches(sym) {
-                        if input.pop().is_some() {
+                    follow_caret = true;
+                    swallowed = None;
+                    if m_escape.matches(sym) && menu.is_some() {
+                        menu.take().unwrap().close(&window);
+                        dirty = true;
+                    }
+                    else if m_escape.matches(sym) {
+                        if banner.take().is_some() {
                             dirty = true;
                         }
-                    } 
-                    else if m_enter.matches(sym) {
-                        match mode {
-                            DeroMode::Default => {
-                                input.push('\n');
-                                dirty = true;
-                            }
-                            DeroMode::Input => {
-                                copy_to_clipboard(&deromanize_escaped(&input));
-                                input.clear();
-                                dirty = true;
-                            }
-                            DeroMode::Lookup => {
-                                let converted = deromanize_escaped(&input);
-                                look_up_word(&converted);
-                                copy_to_clipboard(&converted);
-                                input.clear();
-                                dirty = true;
-                            }
-                        }
-                    } 
-                    else if m_newline.matches(sym) {
-                        input.push('\n');
-                        dirty = true;
-                    } 
+                    }
                     else if m_input.matches(sym) {
-                        match mode {
-                            DeroMode::Input => {
-                                mode = DeroMode::Default;
-                                window.set_title(WINDOW_TITLE);
-                            }
-                            _ => {
-                                mode = DeroMode::Input;
-                                window.set_title(&format!("{} - Input", WINDOW_TITLE));
-                            }
-                        }
+                        let new_mode = match mode {
+                            DeroMode::Input => DeroMode::Default,
+                            _ => DeroMode::Input,
+                        };
+                        switch_mode(&desktop, &mut mode, new_mode);
+                        dirty = true;
                     } 
                     else if m_lookup.matches(sym) {
-                        match mode {
-                            DeroMode::Lookup => {
-                                mode = DeroMode::Default;
-                                window.set_title(WINDOW_TITLE);
-                            }
-                            _ => {
-                                mode = DeroMode::Lookup;
-                                window.set_title(&format!("{} - Look-up", WINDOW_TITLE));
-                            }
-                        }
+                        let new_mode = match mode {
+                            DeroMode::Lookup => DeroMode::Default,
+                            _ => DeroMode::Lookup,
+                        };
+                        switch_mode(&desktop, &mut mode, new_mode);
+                        dirty = true;
+                    }
+                    else if m_romanize.matches(sym) {
+                        let new_mode = match mode {
+                            DeroMode::Romanize => DeroMode::Default,
+                            _ => DeroMode::Romanize,
+                        };
+                        switch_mode(&desktop, &mut mode, new_mode);
+                        dirty = true;
+                    }
+                    else if m_split.matches(sym) && !palette {
+                        split = split.next();
+                        dirty = true;
+                    }
+                    else if m_inspect.matches(sym) && !palette {
+                        inspecting = !inspecting;
+                        dirty = true;
+                    }
+                    else if m_annotate.matches(sym) && !palette {
+                        annotations = !annotations;
+                        dirty = true;
+                    }
+                    else if m_latin.matches(sym) && mode != DeroMode::Romanize {
+                        // The text the keys type isn't wanted.
+                        swallowed = m_latin.text();
+                        converter.toggle_literal(&mut editor);
+                        journal.save(mode, &editor);
+                        dirty = true;
                     }
                     else if m_paste.matches(sym) {
-                        let clip = paste_from_clipboard();
+                        let result = clipboard.paste();
+                        status.output_used(&result);
+                        let clip = report(&mut banner, result).unwrap_or_default();
                         if ! clip.is_empty() {
-                            input.push_str(&clip);
+                            editor.insert(&clip);
+                        }
+                        dirty = true;
+                    }
+                    else if let Some(action) = edit_keys.iter()
+                        .find(|&&(mask, _)| mask.matches(sym))
+                        .map(|&(_, ref action)| action.clone())
+                    {
+                        // Only Return looks up; the commit shortcut just sends the text.
+                        let look_up = mode == DeroMode::Lookup && action == Action::Enter;
+                        match compose::apply(&mut editor, mode, action) {
+                            Effect::Ignored => {}
+                            Effect::Edited => dirty = true,
+                            Effect::Commit => {
+                                let transform = transforms.get(mode);
+                                let converted = converter.convert_in(mode, style, editor.text());
+                                let result = transform.apply(&converter, &converted, editor.text(), mode, style)
+                                    .and_then(|(converted, unrepresentable)| {
+                                        commit(&mut editor, converted, &window, &mut visible,
+                                            outputs.get_mut(mode), look_up)
+                                            .map(|text| (text, unrepresentable))
+                                    });
+                                status.output_used(&result);
+                                if let Some((text, unrepresentable)) = report(&mut banner, result) {
+                                    status.committed(&text);
+                                    if let Some(warning) = transform.warning(&unrepresentable) {
+                                        warn!("{}", warning);
+                                        status.show(warning);
+                                    }
+                                    ipc::notify(&mut subscribers, ipc::commit_event(mode, &text));
+                                }
+                                journal.save(mode, &editor);
+                                dirty = true;
+                            }
+                        }
+                    }
+                }
+                MouseButtonDown(_) if restore_offer.is_some() => {}
+                MouseButtonDown(click) if menu.is_some() => {
+                    // Any click closes the menu, and a left click on an
+                    // item picks it.
+                    let closed = menu.take().unwrap();
+                    let picked = closed.item_at(&font, click.x, click.y);
+                    closed.close(&window);
+                    if let (MouseButton::Left, Some(item)) = (click.button, picked) {
+                        let result = run_menu_item(item, &converter, &editor, mode, style, clipboard);
+                        status.output_used(&result);
+                        report(&mut banner, result);
+                    }
+                    dirty = true;
+                }
+                MouseButtonDown(click) => {
+                    let mapping = mappings.get(editor.text(), (mode, style),
+                        |text| converter.convert_in(mode, style, text));
+                    let (index, offset) = hit_test(&font, mapping, editor.text(), &panes,
+                        click.x, click.y, scroll);
+                    let range = match click.button {
+                        MouseButton::Left if click.clicks >= 3 => Some(mapping.line_at(index)),
+                        // Holding the shortcut key picks just the syllable.
+                        MouseButton::Left if click.clicks == 2 && shortcut_held() => {
+                            Some(mapping.syllable_at(index))
+                        }
+                        MouseButton::Left if click.clicks == 2 => Some(mapping.word_at(index)),
+                        _ => None,
+                    };
+                    if let Some((start, end)) = range {
+                        editor.select(mapping.source_offset(start), mapping.source_offset(end));
+                        dirty = true;
+                    } else if click.button == MouseButton::Left {
+                        if editor.place_caret(offset, shift_held()) {
                             dirty = true;
                         }
+                        dragging = true;
+                    } else if click.button == MouseButton::Right {
+                        menu = Some(ContextMenu::open(&font, &window, click.x, click.y));
+                        dirty = true;
                     }
-                    else if m_clear.matches(sym) {
-                        if ! input.is_empty() {
-                            copy_to_clipboard(&deromanize_escaped(&input));
-                            input.clear();
+                }
+                MouseButtonUp(_) => {
+                    dragging = false;
+                }
+                MouseMotion(motion) => {
+                    if let Some(ref mut menu) = menu {
+                        if menu.hover(&font, motion.x, motion.y) {
+                            dirty = true;
+                        }
+                    } else if dragging {
+                        let mapping = mappings.get(editor.text(), (mode, style),
+                            |text| converter.convert_in(mode, style, text));
+                        let (_, offset) = hit_test(&font, mapping, editor.text(), &panes,
+                            motion.x, motion.y, scroll);
+                        if editor.place_caret(offset, true) {
+                            follow_caret = true;
                             dirty = true;
                         }
                     }
                 }
+                MouseWheel(wheel) if !palette => {
+                    // Positive is away from the user, which scrolls up.
+                    scroll = if wheel.y > 0 {
+                        scroll.saturating_sub(wheel.y as usize)
+                    } else {
+                        scroll + (-wheel.y) as usize
+                    };
+                    follow_caret = false;
+                    dirty = true;
+                }
                 _ => {}
             }
         }
+        if let Some(result) = conversions.convert_next(&converter) {
+            report(&mut banner, result);
+            status.show(conversions.progress());
+            dirty = true;
+        }
+        while let Ok(control) = controls.try_recv() {
+            match control {
+                Control::ToggleWindow if visible => {
+                    window.hide();
+                    visible = false;
+                }
+                Control::ToggleWindow => {
+                    window.show();
+                    window.raise();
+                    visible = true;
+                    dirty = true;
+                }
+                Control::ShowWindow => {
+                    window.show();
+                    window.raise();
+                    visible = true;
+                    dirty = true;
+                }
+                Control::SetMode(new_mode) => {
+                    // The title is showing the restore offer until it's answered.
+                    if restore_offer.is_none() {
+                        switch_mode(&desktop, &mut mode, new_mode);
+                    } else {
+                        mode = new_mode;
+                    }
+                    dirty = true;
+                }
+                Control::Ipc(request, reply) => {
+                    let answer = match request {
+                        Request::Insert(_) | Request::Commit if restore_offer.is_some() => {
+                            Err(String::from("the restore prompt hasn't been answered"))
+                        }
+                        Request::Convert(text) => {
+                            let mut fields = ipc::Map::new();
+                            fields.insert("text".into(), converter.convert(&text).into());
+                            Ok(fields)
+                        }
+                        Request::Forward(text) if restore_offer.is_some() => {
+                            pending_text = Some(pending_text.take().unwrap_or_default() + &text);
+                            Ok(ipc::Map::new())
+                        }
+                        Request::Insert(text) | Request::Forward(text) => {
+                            editor.insert(&text);
+                            journal.save(mode, &editor);
+                            dirty = true;
+                            Ok(ipc::Map::new())
+                        }
+                        Request::GetBuffer => {
+                            let mut fields = ipc::Map::new();
+                            let caret = editor.before_caret().chars().count();
+                            fields.insert("text".into(), editor.text().into());
+                            fields.insert("converted".into(),
+                                converter.convert_in(mode, style, editor.text()).into());
+                            fields.insert("caret".into(), caret.into());
+                            fields.insert("mode".into(), mode.name().into());
+                            Ok(fields)
+                        }
+                        Request::Commit if editor.is_empty() => Err(String::from("nothing to commit")),
+                        Request::Commit => {
+                            let transform = transforms.get(mode);
+                            let converted = converter.convert_in(mode, style, editor.text());
+                            let result = transform.apply(&converter, &converted, editor.text(), mode, style)
+                                .and_then(|(converted, unrepresentable)| {
+                                    commit(&mut editor, converted, &window, &mut visible,
+                                        outputs.get_mut(mode), false)
+                                        .map(|text| (text, unrepresentable))
+                                });
+                            status.output_used(&result);
+                            journal.save(mode, &editor);
+                            dirty = true;
+                            match result {
+                                Ok((text, unrepresentable)) => {
+                                    status.committed(&text);
+                                    if let Some(warning) = transform.warning(&unrepresentable) {
+                                        warn!("{}", warning);
+                                        status.show(warning);
+                                    }
+                                    ipc::notify(&mut subscribers, ipc::commit_event(mode, &text));
+                                    let mut fields = ipc::Map::new();
+                                    fields.insert("text".into(), text.into());
+                                    Ok(fields)
+                                }
+                                Err(err) => {
+                                    let reason = err.to_string();
+                                    warn!("{}", err);
+                                    banner = Some(err);
+                                    Err(reason)
+                                }
+                            }
+                        }
+                        Request::Subscribe(events) => {
+                            subscribers.push(events);
+                            Ok(ipc::Map::new())
+                        }
+                    };
+                    // The client may have hung up already.
+                    let _ = reply.send(answer);
+                }
+                Control::Quit => quit = true,
+            }
+        }
+        if quit {
+            info!("Quitting");
+            if let Some(ref path) = socket {
+                ipc::stop(path);
+            }
+            if window_options.remember_geometry {
+                let (x, y) = window.position();
+                let (width, height) = window.size();
+                Geometry { x: x, y: y, width: width, height: height }.save();
+            }
+            // An unanswered restore offer keeps the old session.
+            if restore_offer.is_none() {
+                if editor.is_empty() {
+                    journal.discard();
+                } else {
+                    journal.save(mode, &editor);
+                }
+            }
+            break 'main;
+        }
+        if restore_offer.is_none() {
+            journal.tick(mode, &editor);
+        }
+        if rules_checked.elapsed() >= rules::CHECK_INTERVAL {
+            rules_checked = Instant::now();
+            let reloaded = rule_file.as_mut().and_then(|rule_file| rule_file.reload());
+            if let Some(rules) = reloaded.and_then(|result| report(&mut banner, result)) {
+                info!("Rules reloaded");
+                converter.rules = rules;
+                mappings.clear();
+                dirty = true;
+            }
+        }
+        if dirty {
+            // Edits show the caret right away, and restart its blinking.
+            caret_on = true;
+            blink_start = Instant::now();
+        } else if theme.blink_rate > 0 {
+            let elapsed = blink_start.elapsed();
+            let millis = elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000;
+            let on = (millis / theme.blink_rate) % 2 == 0;
+            if on != caret_on {
+                caret_on = on;
+                dirty = true;
+            }
+        }
         if dirty {
-            renderer.color(clear_color).clear().unwrap();
+            let frame_start = Instant::now();
+            if let Err(err) = renderer.color(theme.background).clear() {
+                banner = Some(DerowinError::Render(format!("{:?}", err)));
+            }
             
-            let mut converted = deromanize_escaped(&input);
-            converted.push('_');
-            let line_gap = font.v_metrics(Scale::uniform(FONT_POINT_SIZE)).line_gap;
-            let ascent = font.v_metrics(Scale::uniform(FONT_POINT_SIZE)).ascent;
-            let line_skip = if line_gap != 0.0 {
-                line_gap.ceil() as i32
+            let metrics = Metrics::of(&font);
+            trace!("Line height: {}, Line skip: {}", metrics.height, metrics.line_skip);
+            let window_size = window.size();
+            let status_top = if palette {
+                window_size.1
             } else {
-                (ascent * 1.25).ceil() as i32
+                window_size.1 - StatusBar::height(&font)
             };
-            //println!("Line gap: {}, Ascent: {}, Line skip: {}", line_gap, ascent, line_skip);
-            for (i, line) in converted.lines().enumerate() {
-                if line == "" {
-                    continue;
+            let (x, mut y) = theme.padding;
+            // A strip along the input area shows the mode at a glance.
+            let accent = render::fill_rect(&renderer, theme.accent(mode), 0, 0, 3, status_top);
+            // The banner takes the first line, pushing the text down.
+            if let Some(ref err) = banner {
+                let message = format!("{} (Escape to dismiss)", err);
+                if let Err(err) = render::draw_text(&renderer, &font, &message, theme.error, x, y) {
+                    error!("Could not show error banner: {}", err);
                 }
-                let surf = rsdl2_font::render(&font, line, (0, 0, 0), FONT_POINT_SIZE).expect("Rendering failed");
-                let tex = renderer.create_texture_from_surface(&surf).expect("Could not create texture");
-                let y_indent = i as i32 * line_skip;
-                let texpos = tex.rect_at(TEXT_POS.0, TEXT_POS.1 + y_indent);
-                renderer.copy(&tex, None, Some(texpos)).unwrap();
+                y += metrics.line_skip;
             }
+            let mut resized = false;
+            let drawn = match restore_offer {
+                Some(_) => {
+                    render::draw_text(&renderer, &font, "Restore previous session?",
+                        theme.foreground, x, y)
+                    .and_then(|_| render::draw_text(&renderer, &font,
+                        "Return: restore, Escape: discard",
+                        theme.foreground, x, y + metrics.line_skip))
+                    .map(|_| ())
+                }
+                None => {
+                    let source = editor.text();
+                    // What is shown is the whole text converted, as it would
+                    // be committed, with the caret and the selection placed
+                    // through its mapping.
+                    let (before, after, selection) = {
+                        let mapping = mappings.get(source, (mode, style),
+                            |text| converter.convert_in(mode, style, text));
+                        let (before, after) = mapping.split(editor.caret());
+                        let selection = editor.selection().map(|(start, end)| {
+                            (mapping.converted_index(start), mapping.converted_index(end))
+                        });
+                        (before, after, selection)
+                    };
+                    let literal = converter.literal_in(mode, source);
+                    let converted = before.clone() + &after;
+                    // The inspector goes along the bottom, above the status bar.
+                    let inspecting = inspecting && !palette;
+                    let text_bottom = if inspecting {
+                        status_top - inspector_height(&font, &theme)
+                    } else {
+                        status_top
+                    };
+                    // The source pane comes first, and the output is beside
+                    // or below it.
+                    let split = if palette { Split::Off } else { split };
+                    let (output, pane_bottom) = match split {
+                        Split::Off => ((x, y), text_bottom),
+                        Split::Below => {
+                            let middle = y + (text_bottom - y) / 2;
+                            ((x, middle + theme.padding.1), middle)
+                        }
+                        Split::Side => ((window_size.0 / 2 + x, y), text_bottom),
+                    };
+                    panes = Panes {
+                        source: if split == Split::Off { None } else { Some((x, y)) },
+                        output: output,
+                    };
+                    // Where each pane ends. The palette grows to fit its text.
+                    let source_clip = match split {
+                        Split::Side => (window_size.0 / 2, text_bottom),
+                        _ => (window_size.0, pane_bottom),
+                    };
+                    let output_clip = if palette {
+                        (i32::MAX, i32::MAX)
+                    } else {
+                        (window_size.0, text_bottom)
+                    };
+                    // Everything fits in the palette, so it never scrolls.
+                    if palette {
+                        scroll = 0;
+                    } else {
+                        let rows = ((pane_bottom - y) / metrics.line_skip).max(1) as usize;
+                        let lines = converted.matches('\n').count() + 1;
+                        let caret_line = before.matches('\n').count();
+                        if follow_caret {
+                            scroll = scroll.min(caret_line).max((caret_line + 1).saturating_sub(rows));
+                        }
+                        scroll = scroll.min(lines.saturating_sub(rows));
+                    }
+                    // Romanize mode keeps nothing literal.
+                    let converting = mode != DeroMode::Romanize;
+                    let latin = converting && converter.in_literal(&editor);
+                    if title_shown != Some((mode, latin)) {
+                        window.set_title(&title(mode, latin));
+                        title_shown = Some((mode, latin));
+                    }
+                    let mut view = BufferView {
+                        before: &before,
+                        after: &after,
+                        preedit: &preedit,
+                        selection: selection,
+                        marked: None,
+                        literal: literal,
+                        converted: converting,
+                        show_caret: caret_on,
+                        literal_caret: latin,
+                        scroll: scroll,
+                        clip: output_clip,
+                    };
+                    // The syllable before the caret, by converted index, and
+                    // what it was typed as, by source offset.
+                    let caret_syllable = if panes.source.is_some() || inspecting {
+                        let mapping = mappings.get(source, (mode, style),
+                            |text| converter.convert_in(mode, style, text));
+                        let caret = mapping.converted_index(editor.before_caret().len());
+                        let (start, end) = mapping.syllable_at(caret.saturating_sub(1));
+                        Some(((start, end), (mapping.source_offset(start), mapping.source_offset(end))))
+                    } else {
+                        None
+                    };
+                    let inspected = match caret_syllable {
+                        Some(((start, end), (source_start, source_end))) if inspecting => {
+                            let converted_part = converted.chars().skip(start).take(end - start)
+                                .collect::<String>();
+                            let source_part = &source[source_start..source_end];
+                            // In romanize mode, the syllable is in the source.
+                            let inspection = match mode {
+                                DeroMode::Romanize => source_part.chars().next()
+                                    .map(|ch| Inspection::of(ch, &converted_part)),
+                                _ => converted_part.chars().next()
+                                    .map(|ch| Inspection::of(ch, source_part)),
+                            };
+                            render::fill_rect(&renderer, theme.accent(mode), 0, text_bottom,
+                                window_size.0, 1)
+                                .and_then(|_| draw_inspector(&renderer, &font, &theme,
+                                    inspection.as_ref(), x, text_bottom))
+                        }
+                        _ => Ok(()),
+                    };
+                    // Both panes underline the syllable before the caret, and
+                    // what it was typed as.
+                    let source_drawn = match (panes.source, caret_syllable) {
+                        (Some((source_x, source_y)), Some((marked, source_marked))) => {
+                            let (source_start, source_end) = source_marked;
+                            let chars = |offset: usize| source[..offset].chars().count();
+                            view.marked = Some(marked);
+                            let source_view = BufferView {
+                                before: editor.before_caret(),
+                                after: editor.after_caret(),
+                                preedit: &preedit,
+                                selection: editor.selection()
+                                    .map(|(start, end)| (chars(start), chars(end))),
+                                marked: Some((chars(source_start), chars(source_end))),
+                                literal: if converting {
+                                    converter.literal_parts(source).into_iter()
+                                        .map(|(start, end)| (chars(start), chars(end)))
+                                        .collect()
+                                } else {
+                                    Vec::new()
+                                },
+                                converted: !converting,
+                                show_caret: caret_on,
+                                literal_caret: latin,
+                                scroll: scroll,
+                                clip: source_clip,
+                            };
+                            let divider = match split {
+                                Split::Side => render::fill_rect(&renderer, theme.accent(mode),
+                                    window_size.0 / 2, 0, 1, text_bottom),
+                                _ => render::fill_rect(&renderer, theme.accent(mode),
+                                    0, pane_bottom, window_size.0, 1),
+                            };
+                            divider.and_then(|_| render::draw_buffer(&renderer, &font, &theme,
+                                &source_view, source_x, source_y)).map(|_| ())
+                        }
+                        _ => Ok(()),
+                    }.and(inspected);
+                    let (x, y) = panes.output;
+                    if palette {
+                        let shown = before.clone() + &preedit + &after;
+                        let mut size = palette_size(&font, &theme, &shown, y);
+                        // An open menu keeps the palette big enough for it.
+                        if let Some(ref menu) = menu {
+                            let extent = menu.extent(&font);
+                            size = (size.0.max(extent.0), size.1.max(extent.1));
+                        }
+                        if size != window_size {
+                            window.set_size(size.0, size.1);
+                            resized = true;
+                        }
+                        render::draw_buffer(&renderer, &font, &theme, &view, x, y)
+                    } else {
+                        // The annotated preview has no caret or selection.
+                        let output_drawn = if annotations {
+                            let mapping = mappings.get(source, (mode, style),
+                                |text| converter.convert_in(mode, style, text));
+                            let segments = annotate::segments(mapping, source,
+                                mode == DeroMode::Romanize);
+                            render::draw_annotated(&renderer, &font, &theme, &segments,
+                                &view.literal, scroll, output_clip, x, y)
+                        } else {
+                            render::draw_buffer(&renderer, &font, &theme, &view, x, y)
+                        };
+                        source_drawn
+                            .and(output_drawn)
+                            .and_then(|caret| status.draw(&renderer, &font, &theme, mode,
+                                outputs.get(mode), &converted, window_size).map(|_| caret))
+                    }.map(|(caret_x, caret_y)| {
+                        // Input method candidates go just under the caret.
+                        let rect = Rect::new(caret_x, caret_y, 1, metrics.height as u32);
+                        if input_rect != Some(rect) {
+                            video_context.set_text_input_rect(rect);
+                            input_rect = Some(rect);
+                        }
+                    })
+                }
+            }.and(accent);
+            // The menu goes over everything else.
+            let drawn = match menu {
+                Some(ref menu) => drawn.and(menu.draw(&renderer, &font, &theme)),
+                None => drawn,
+            };
             
             renderer.present();
-            dirty = false;
+            // Drawn again at the new size.
+            dirty = resized;
+            trace!("Frame drawn in {:?}", frame_start.elapsed());
+            // Shown on the next frame, since the banner is already drawn.
+            if let Err(err) = drawn {
+                warn!("{}", err);
+                if banner.is_none() {
+                    banner = Some(err);
+                    dirty = true;
+                }
+            }
         }
         
         thread::sleep(Duration::from_millis(1000 / MAX_FPS as u64));
     }
+    Ok(())
 }
 
-#[cfg(target_os = "macos")]
-fn copy_to_clipboard(text: &str) {
-    // println!("Copying '{}' to the clipboard...", text);
-    println!("{}", text);
-    let mut child = Command::new("/usr/bin/pbcopy")
-        .arg(text)
-        .stdin(Stdio::piped())
-        .spawn()
-        .expect("Could not run pbcopy");
-    if let Some(ref mut stdin) = child.stdin {
-        stdin.write_all(text.as_bytes())
-            .expect("Could not write to pbcopy");
+/// Switches modes, keeping the title and the tray in step.
+fn switch_mode(desktop: &Desktop, mode: &mut DeroMode, new_mode: DeroMode) {
+    *mode = new_mode;
+    info!("Switched to {} mode", new_mode.name());
+    desktop.set_mode(new_mode);
+}
+
+/// The window title for the mode, which says when literal Latin is being
+/// typed. It is set when the window is drawn.
+fn title(mode: DeroMode, latin: bool) -> String {
+    if latin {
+        format!("{} (Latin)", mode.title())
     } else {
-        unreachable!();
+        mode.title()
+    }
+}
+
+/// The window size that fits the converted text in the palette layout,
+/// with the text starting at `top`.
+fn palette_size(font: &Font, theme: &Theme, converted: &str, top: i32) -> (i32, i32) {
+    let metrics = Metrics::of(font);
+    let caret_width = (render::FONT_POINT_SIZE / 2.0).ceil() as i32;
+    let widest = converted.split('\n')
+        .map(|line| render::text_width(font, line))
+        .max()
+        .unwrap_or(0);
+    let lines = converted.split('\n').count() as i32;
+    let width = (widest + caret_width + 2 * theme.padding.0).max(PALETTE_MIN_WIDTH);
+    let height = top + (lines - 1) * metrics.line_skip + metrics.height + theme.padding.1;
+    (width, height)
+}
+
+/// Loads the first of the given fonts that exists.
+fn load_font(paths: &[&str]) -> Result<Font<'static>, DerowinError> {
+    for &path in paths {
+        let mut fontbuf = Vec::new();
+        match File::open(path).and_then(|mut file| file.read_to_end(&mut fontbuf)) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("Font not found: {}", path);
+                continue;
+            }
+            Err(e) => return Err(DerowinError::FontIo(path.to_string(), e)),
+        }
+        info!("Using font {}", path);
+        let collection = FontCollection::from_bytes(fontbuf);
+        return collection.font_at(0).ok_or_else(|| DerowinError::FontFormat(path.to_string()));
     }
-    child.wait().expect("Error while running pbcopy");
+    Err(DerowinError::FontMissing(paths.iter().map(|p| p.to_string()).collect()))
 }
 
+/// Puts a runtime error in the banner, so that the session can go on.
+fn report<T>(banner: &mut Option<DerowinError>, result: Result<T, DerowinError>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("{}", err);
+            *banner = Some(err);
+            None
+        }
+    }
+}
+
+/// Sends the converted buffer to the mode's output (looking it up first if
+/// asked), only clearing the buffer if that worked. Typing hides the window
+/// first, so that the text goes to the window that had the focus, and shows
+/// it again if that failed. Returns the text.
+fn commit(editor: &mut Editor, converted: String, window: &Window, visible: &mut bool,
+    sink: &mut Sink, look_up: bool)
+    -> Result<String, DerowinError>
+{
+    if look_up {
+        look_up_word(&converted)?;
+    }
+    if sink.types() && *visible {
+        window.hide();
+        *visible = false;
+    }
+    if let Err(err) = sink.send(&converted) {
+        if !*visible {
+            window.show();
+            window.raise();
+            *visible = true;
+        }
+        return Err(err);
+    }
+    info!("Committed {}", redact(&converted));
+    editor.take();
+    Ok(converted)
+}
+
+/// `derowin rules test`: checks that the rules file reads, then converts
+/// its tests and any text given, with and without the rules.
+fn run_rules(plain: &Converter, rule_file: Option<&RuleFile>, args: &[String])
+    -> Result<(), DerowinError>
+{
+    match args.first().map(|arg| arg.as_str()) {
+        Some("test") => {}
+        Some(command) => {
+            return Err(DerowinError::Usage(format!("Unknown rules command '{}'", command)));
+        }
+        None => return Err(DerowinError::Usage(String::from("Usage: derowin rules test [TEXT...]"))),
+    }
+    let path = rule_file.map(|rule_file| rule_file.path())
+        .ok_or_else(|| DerowinError::Config(String::from("No rules file to test")))?;
+    let rules = Rules::load(path)?;
+    let mut converter = plain.clone();
+    converter.rules = Some(rules.clone());
+    println!("{}: {} abbreviations, {} overrides, {} protected words", path.display(),
+        rules.abbreviations.len(), rules.overrides.len(), rules.protected.len());
+    let mut failed = 0;
+    for &(ref text, ref expected) in &rules.tests {
+        let converted = converter.convert(text);
+        if &converted == expected {
+            println!("ok      {} -> {}", text, converted);
+        } else {
+            println!("FAILED  {} -> {} (expected {})", text, converted, expected);
+            failed += 1;
+        }
+    }
+    for text in &args[1..] {
+        println!("{} -> {} (without rules: {})", text, converter.convert(text),
+            plain.convert(text));
+    }
+    if failed > 0 {
+        return Err(DerowinError::Config(
+            format!("{} of {} rule tests failed", failed, rules.tests.len())));
+    }
+    Ok(())
+}
+
+/// How tall the inspector panel is.
+fn inspector_height(font: &Font, theme: &Theme) -> i32 {
+    INSPECTOR_LINES * Metrics::of(font).line_skip + 2 * theme.padding.1
+}
+
+/// Shows what the syllable at the caret is made of, in a panel that starts
+/// at `top`.
+fn draw_inspector(renderer: &Renderer, font: &Font, theme: &Theme,
+    inspection: Option<&Inspection>, x: i32, top: i32)
+    -> Result<(), DerowinError>
+{
+    let line_skip = Metrics::of(font).line_skip;
+    let lines = match inspection {
+        Some(inspection) => inspection.lines(),
+        None => vec![String::from("Nothing at the caret")],
+    };
+    for (i, line) in lines.iter().enumerate() {
+        render::draw_text(renderer, font, line, theme.foreground, x,
+            top + theme.padding.1 + i as i32 * line_skip)?;
+    }
+    Ok(())
+}
+
+/// Where the panes of the buffer start in the window.
+#[derive(Debug, Clone, Copy)]
+struct Panes {
+    /// The romanized source, when the window is split.
+    source: Option<(i32, i32)>,
+    output: (i32, i32),
+}
+
+/// The character boundary nearest to a point in the window, in whichever
+/// pane it is, with `scroll` lines out of view. Gives both its converted
+/// index and its offset in the source, which is exact in the source pane.
+fn hit_test(font: &Font, mapping: &Mapping, source: &str, panes: &Panes, x: i32, y: i32,
+    scroll: usize)
+    -> (usize, usize)
+{
+    let line_skip = Metrics::of(font).line_skip;
+    let (output_left, output_top) = panes.output;
+    let in_source = panes.source.map_or(false, |(_, top)| {
+        if output_top > top { y < output_top } else { x < output_left }
+    });
+    match panes.source {
+        Some((left, top)) if in_source => {
+            let line = ((y - top).max(0) / line_skip) as usize + scroll;
+            let index = render::char_at(font, source, line, x, left);
+            let offset = source.char_indices().nth(index).map_or(source.len(), |(i, _)| i);
+            (mapping.converted_index(offset), offset)
+        }
+        _ => {
+            let line = ((y - output_top).max(0) / line_skip) as usize + scroll;
+            let index = render::char_at(font, mapping.converted(), line, x, output_left);
+            (index, mapping.source_offset(index))
+        }
+    }
+}
+
+// rsdl2 only has the modifiers in key events, and mouse events need them
+// too.
+extern "C" {
+    fn SDL_GetModState() -> u32;
+}
+
+/// SDL's modifier bits, for either side of the keyboard.
+mod held {
+    pub const SHIFT: u32 = 0x0003;
+    #[cfg(not(target_os = "macos"))]
+    pub const CTRL: u32 = 0x00c0;
+    #[cfg(target_os = "macos")]
+    pub const GUI: u32 = 0x0c00;
+}
+
+fn modifier_held(modifier: u32) -> bool {
+    unsafe { SDL_GetModState() & modifier != 0 }
+}
+
+fn shift_held() -> bool {
+    modifier_held(held::SHIFT)
+}
+
+/// Whether the shortcut modifier is held now.
 #[cfg(target_os = "macos")]
-fn look_up_word(text: &str) {
+fn shortcut_held() -> bool {
+    modifier_held(held::GUI)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn shortcut_held() -> bool {
+    modifier_held(held::CTRL)
+}
+
+/// Does what a context menu item says to the selection, or to the whole
+/// buffer if nothing is selected.
+fn run_menu_item(item: MenuItem, converter: &Converter, editor: &Editor, mode: DeroMode,
+    style: Style, clipboard: Backend)
+    -> Result<(), DerowinError>
+{
+    let source = match editor.selected_text() {
+        "" => editor.text(),
+        selected => selected,
+    };
+    let converted = converter.convert_in(mode, style, source);
+    // In romanize mode, the buffer is the Hangul.
+    let (hangul, romanized) = match mode {
+        DeroMode::Romanize => (source, converted.as_str()),
+        _ => (converted.as_str(), source),
+    };
+    match item {
+        MenuItem::Copy => clipboard.copy(&converted),
+        MenuItem::CopyRomanized => clipboard.copy(romanized),
+        MenuItem::LookUp => look_up_word(hangul),
+        MenuItem::Hanja => look_up_hanja(hangul),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn look_up_word(text: &str) -> Result<(), DerowinError> {
     let url = format!("dict://{}", &text);
-    Command::new("open")
+    process::Command::new("open")
         .arg(&url)
         .status()
-        .expect("Could not open dictionary app");
+        .and_then(clipboard::check_status)
+        .map_err(DerowinError::Lookup)
 }
 
-#[cfg(target_os = "macos")]
-fn paste_from_clipboard() -> String {
-    let output = Command::new("pbpaste").output().expect("failed to run pbpaste");
-    String::from_utf8_lossy(&output.stdout).to_string()
+#[cfg(not(target_os = "macos"))]
+fn look_up_word(_text: &str) -> Result<(), DerowinError> {
+    debug!("Dictionary look-up is only supported on macOS");
+    Ok(())
 }
 
-#[cfg(not(target_os = "macos"))]
-fn copy_to_clipboard(text: &str) {}
+/// Opens the Hanja dictionary on the text in the browser.
+fn look_up_hanja(text: &str) -> Result<(), DerowinError> {
+    let mut query = String::new();
+    for byte in text.trim().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                query.push(byte as char);
+            }
+            _ => query.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    let url = format!("https://hanja.dict.naver.com/#/search?query={}", query);
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    process::Command::new(opener)
+        .arg(&url)
+        .status()
+        .and_then(clipboard::check_status)
+        .map_err(DerowinError::Lookup)
+}
 
-#[cfg(not(target_os = "macos"))]
-fn look_up_word(text: &str) {}
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
 
-#[cfg(not(target_os = "macos"))]
-fn paste_from_clipboard() -> String { String::new() }
+    use compose::Converter;
+    use rules::RuleFile;
+
+    use super::run_rules;
+
+    /// Runs `derowin rules test` on a rules file with the given tests.
+    fn rules_test(name: &str, tests: &str) -> bool {
+        let path = env::temp_dir().join(format!("derowin-{}-{}", name, process::id()));
+        let contents = format!("[abbreviations]\nkr = hangug\n\n[tests]\n{}", tests);
+        fs::write(&path, contents).unwrap();
+        let rule_file = RuleFile::new(path.clone());
+        let passed = run_rules(&Converter::new(), Some(&rule_file), &[String::from("test")]).is_ok();
+        fs::remove_file(&path).unwrap();
+        passed
+    }
+
+    #[test]
+    fn rules_test_expands_hyphenated_abbreviations() {
+        let expected = Converter::new().convert("hangug-eo");
+        assert!(rules_test("rules-pass", &format!("kr-eo = {}\n", expected)));
+    }
+
+    #[test]
+    fn rules_test_fails_on_a_wrong_conversion() {
+        assert!(!rules_test("rules-fail", "kr-eo = kr-eo\n"));
+    }
+
+    #[test]
+    fn rules_test_needs_a_command_and_a_file() {
+        let plain = Converter::new();
+        assert!(run_rules(&plain, None, &[String::from("test")]).is_err());
+        assert!(run_rules(&plain, None, &[]).is_err());
+        assert!(run_rules(&plain, None, &[String::from("check")]).is_err());
+    }
+}
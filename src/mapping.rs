@@ -0,0 +1,243 @@
+//! Where the characters of the converted text come from in the romanized
+//! text, so that a position in what is shown can be found in the buffer.
+//!
+//! dero doesn't say which letters made which syllable, so the mapping is
+//! found by converting ever longer beginnings of each line: a converted
+//! character starts where the first beginning that converts to everything
+//! before it ends.
+
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    converted: String,
+    /// The romanized byte offset of each converted character boundary,
+    /// from the start of the text to its end.
+    offsets: Vec<usize>,
+}
+
+impl Mapping {
+    /// Maps text through a conversion, like `compose::convert`. The whole
+    /// text is converted at once, so that the mapping is of exactly what
+    /// would be committed, and then mapped a line at a time.
+    pub fn new<F: Fn(&str) -> String>(text: &str, convert: F) -> Mapping {
+        let whole = convert(text);
+        // Conversions keep line breaks; one that didn't is mapped line by
+        // line instead.
+        let lines = if whole.matches('\n').count() == text.matches('\n').count() {
+            whole.split('\n').map(String::from).collect::<Vec<_>>()
+        } else {
+            text.split('\n').map(|line| convert(line)).collect()
+        };
+        let mut converted = String::with_capacity(whole.len());
+        let mut offsets = Vec::new();
+        let mut line_start = 0;
+        for (i, (line, line_converted)) in text.split('\n').zip(lines).enumerate() {
+            if i > 0 {
+                converted.push('\n');
+            }
+            let expected = line_converted.chars().collect::<Vec<_>>();
+            let mut boundaries = line.char_indices().map(|(j, _)| j)
+                .chain(Some(line.len()))
+                .peekable();
+            let mut found = 0;
+            offsets.push(line_start);
+            // The end of the converted line is the end of the line.
+            'chars: for count in 1..expected.len() {
+                while let Some(&j) = boundaries.peek() {
+                    let prefix = convert(&line[..j]);
+                    if prefix.chars().take(count).eq(expected[..count].iter().cloned()) {
+                        offsets.push(line_start + j);
+                        found = count;
+                        continue 'chars;
+                    }
+                    boundaries.next();
+                }
+                break;
+            }
+            // Along with anything that couldn't be placed.
+            for _ in found..expected.len() {
+                offsets.push(line_start + line.len());
+            }
+            converted.push_str(&line_converted);
+            line_start += line.len() + 1;
+        }
+        Mapping {
+            converted: converted,
+            offsets: offsets,
+        }
+    }
+
+    /// The whole text, converted.
+    pub fn converted(&self) -> &str {
+        &self.converted
+    }
+
+    /// The romanized byte offset of a converted character boundary.
+    pub fn source_offset(&self, index: usize) -> usize {
+        self.offsets.get(index).or_else(|| self.offsets.last()).cloned().unwrap_or(0)
+    }
+
+    /// The converted text on either side of a romanized byte offset.
+    pub fn split(&self, offset: usize) -> (String, String) {
+        let index = self.converted_index(offset);
+        let at = self.converted.char_indices().nth(index).map_or(self.converted.len(), |(i, _)| i);
+        (self.converted[..at].to_string(), self.converted[at..].to_string())
+    }
+
+    /// The last converted character boundary at or before a romanized byte
+    /// offset.
+    pub fn converted_index(&self, offset: usize) -> usize {
+        match self.offsets.binary_search(&offset) {
+            Ok(mut index) => {
+                // Characters that couldn't be placed share an offset.
+                while index > 0 && self.offsets[index - 1] == offset {
+                    index -= 1;
+                }
+                index
+            }
+            Err(index) => index.saturating_sub(1),
+        }
+    }
+
+    /// The converted character at or after a boundary (before it at the end
+    /// of a line), as a range.
+    pub fn syllable_at(&self, index: usize) -> (usize, usize) {
+        let chars = self.converted.chars().collect::<Vec<_>>();
+        match chars.get(index) {
+            Some(&ch) if ch != '\n' => (index, index + 1),
+            _ if index > 0 && chars[index - 1] != '\n' => (index - 1, index),
+            _ => (index, index),
+        }
+    }
+
+    /// The eojeol (the run of characters between spaces) around a
+    /// boundary, as a range of converted characters.
+    pub fn word_at(&self, index: usize) -> (usize, usize) {
+        self.run_at(index, char::is_whitespace)
+    }
+
+    /// The line around a boundary, as a range of converted characters.
+    pub fn line_at(&self, index: usize) -> (usize, usize) {
+        self.run_at(index, |ch| ch == '\n')
+    }
+
+    fn run_at<F: Fn(char) -> bool>(&self, index: usize, separator: F) -> (usize, usize) {
+        let chars = self.converted.chars().collect::<Vec<_>>();
+        let index = index.min(chars.len());
+        let start = chars[..index].iter().rposition(|&ch| separator(ch)).map_or(0, |i| i + 1);
+        let end = chars[index..].iter().position(|&ch| separator(ch))
+            .map_or(chars.len(), |i| index + i);
+        (start, end)
+    }
+}
+
+/// The mapping of the text last asked for, kept until the text or the way
+/// it is converted changes, since making one converts the text over and
+/// over.
+#[derive(Debug, Clone)]
+pub struct Cache<K> {
+    last: Option<(String, K, Mapping)>,
+}
+
+impl<K: PartialEq> Cache<K> {
+    pub fn new() -> Cache<K> {
+        Cache { last: None }
+    }
+
+    /// The mapping of text through the conversion that `key` stands for,
+    /// which is only made if the last one was for other text or another
+    /// key.
+    pub fn get<F: Fn(&str) -> String>(&mut self, text: &str, key: K, convert: F) -> &Mapping {
+        let fresh = match self.last {
+            Some((ref last_text, ref last_key, _)) => last_text == text && *last_key == key,
+            None => false,
+        };
+        if !fresh {
+            self.last = Some((text.to_string(), key, Mapping::new(text, convert)));
+        }
+        match self.last {
+            Some((_, _, ref mapping)) => mapping,
+            None => unreachable!(),
+        }
+    }
+
+    /// Forgets the last mapping, for when the conversion itself changed.
+    pub fn clear(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::{Cache, Mapping};
+
+    /// Stands in for dero, with a syllable for each of two spellings.
+    fn convert(text: &str) -> String {
+        text.replace("ga", "가").replace("na", "나")
+    }
+
+    #[test]
+    fn maps_converted_characters_to_the_source() {
+        let mapping = Mapping::new("gana ga\nna", convert);
+        assert_eq!(mapping.converted(), "가나 가\n나");
+        let offsets = (0..7).map(|i| mapping.source_offset(i)).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![0, 2, 4, 5, 7, 8, 10]);
+        assert_eq!(mapping.converted_index(2), 1);
+        assert_eq!(mapping.converted_index(3), 1);
+        assert_eq!(mapping.converted_index(10), 6);
+    }
+
+    #[test]
+    fn finds_syllables_words_and_lines() {
+        let mapping = Mapping::new("gana ga\nna", convert);
+        assert_eq!(mapping.syllable_at(1), (1, 2));
+        // At the end of a line, the syllable before it.
+        assert_eq!(mapping.syllable_at(4), (3, 4));
+        assert_eq!(mapping.word_at(1), (0, 2));
+        assert_eq!(mapping.line_at(1), (0, 4));
+        assert_eq!(mapping.line_at(6), (5, 6));
+    }
+
+    #[test]
+    fn splits_whole_syllables_at_a_caret_inside_one() {
+        // A final joins the syllable before it, as dero does.
+        let joining = |text: &str| convert(text).replace("가n", "간");
+        let mapping = Mapping::new("gan\nna", joining);
+        assert_eq!(mapping.split(2), (String::new(), String::from("간\n나")));
+        assert_eq!(mapping.split(3), (String::from("간"), String::from("\n나")));
+    }
+
+    #[test]
+    fn unplaced_characters_go_at_the_end() {
+        // Nothing short of the whole text converts to its first character.
+        let mapping = Mapping::new("ab", |text: &str| {
+            if text == "ab" { String::from("xy") } else { String::new() }
+        });
+        assert_eq!(mapping.source_offset(1), 2);
+        assert_eq!(mapping.converted_index(2), 1);
+    }
+
+    #[test]
+    fn cache_only_maps_new_text() {
+        let calls = Cell::new(0);
+        let counted = |text: &str| {
+            calls.set(calls.get() + 1);
+            convert(text)
+        };
+        let mut cache = Cache::new();
+        cache.get("gana", 1, &counted);
+        let made = calls.get();
+        assert_eq!(cache.get("gana", 1, &counted).converted(), "가나");
+        assert_eq!(calls.get(), made);
+        cache.get("gana", 2, &counted);
+        assert!(calls.get() > made);
+        let made = calls.get();
+        assert_eq!(cache.get("na", 2, &counted).converted(), "나");
+        assert!(calls.get() > made);
+        let made = calls.get();
+        cache.clear();
+        cache.get("na", 2, &counted);
+        assert!(calls.get() > made);
+    }
+}
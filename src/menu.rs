@@ -0,0 +1,128 @@
+//! The menu that right-clicking opens, drawn inside the window.
+
+use rsdl2::{Renderer, Window};
+use rsdl2_font::rusttype::Font;
+
+use error::DerowinError;
+use render::{self, Metrics};
+use theme::Theme;
+
+/// Space around the menu items, in pixels.
+const MENU_PADDING: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuItem {
+    /// Copies the converted selection, or the whole buffer.
+    Copy,
+    /// Copies the romanization itself.
+    CopyRomanized,
+    LookUp,
+    /// Looks up the Hanja for the text.
+    Hanja,
+}
+
+impl MenuItem {
+    pub const ALL: [MenuItem; 4] = [
+        MenuItem::Copy, MenuItem::CopyRomanized, MenuItem::LookUp, MenuItem::Hanja,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            MenuItem::Copy => "Copy",
+            MenuItem::CopyRomanized => "Copy romanized",
+            MenuItem::LookUp => "Look up",
+            MenuItem::Hanja => "Hanja",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextMenu {
+    x: i32,
+    y: i32,
+    hovered: Option<MenuItem>,
+    /// The window's size before it grew to fit the menu.
+    grown_from: Option<(i32, i32)>,
+}
+
+impl ContextMenu {
+    /// Opens the menu at a point, moved to fit in the window. A window
+    /// too small for the menu grows until it is closed.
+    pub fn open(font: &Font, window: &Window, x: i32, y: i32) -> ContextMenu {
+        let (width, height) = ContextMenu::size(font);
+        let size = window.size();
+        let fitted = (size.0.max(width), size.1.max(height));
+        let grown_from = if fitted != size {
+            window.set_size(fitted.0, fitted.1);
+            Some(size)
+        } else {
+            None
+        };
+        ContextMenu {
+            x: x.min(fitted.0 - width).max(0),
+            y: y.min(fitted.1 - height).max(0),
+            hovered: None,
+            grown_from: grown_from,
+        }
+    }
+
+    /// Closes the menu, giving the window back the size it had.
+    pub fn close(self, window: &Window) {
+        if let Some((width, height)) = self.grown_from {
+            window.set_size(width, height);
+        }
+    }
+
+    /// How big the window has to be to show the whole menu.
+    pub fn extent(&self, font: &Font) -> (i32, i32) {
+        let (width, height) = ContextMenu::size(font);
+        (self.x + width, self.y + height)
+    }
+
+    fn size(font: &Font) -> (i32, i32) {
+        let widest = MenuItem::ALL.iter()
+            .map(|item| render::text_width(font, item.label()))
+            .max()
+            .unwrap_or(0);
+        let rows = MenuItem::ALL.len() as i32;
+        (widest + 2 * MENU_PADDING, rows * Metrics::of(font).line_skip + 2 * MENU_PADDING)
+    }
+
+    pub fn item_at(&self, font: &Font, x: i32, y: i32) -> Option<MenuItem> {
+        let (width, _) = ContextMenu::size(font);
+        if x < self.x || x >= self.x + width || y < self.y + MENU_PADDING {
+            return None;
+        }
+        let row = (y - self.y - MENU_PADDING) / Metrics::of(font).line_skip;
+        MenuItem::ALL.get(row as usize).cloned()
+    }
+
+    /// Highlights the item under the pointer, returning whether that
+    /// changed.
+    pub fn hover(&mut self, font: &Font, x: i32, y: i32) -> bool {
+        let hovered = self.item_at(font, x, y);
+        let changed = hovered != self.hovered;
+        self.hovered = hovered;
+        changed
+    }
+
+    pub fn draw(&self, renderer: &Renderer, font: &Font, theme: &Theme)
+        -> Result<(), DerowinError>
+    {
+        let (width, height) = ContextMenu::size(font);
+        let line_skip = Metrics::of(font).line_skip;
+        render::fill_rect(renderer, theme.foreground, self.x, self.y, width, height)?;
+        render::fill_rect(renderer, theme.background, self.x + 1, self.y + 1, width - 2,
+            height - 2)?;
+        for (i, item) in MenuItem::ALL.iter().enumerate() {
+            let top = self.y + MENU_PADDING + i as i32 * line_skip;
+            if self.hovered == Some(*item) {
+                render::fill_rect(renderer, theme.selection, self.x + 1, top, width - 2,
+                    line_skip)?;
+            }
+            render::draw_text(renderer, font, item.label(), theme.foreground,
+                self.x + MENU_PADDING, top)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! The forms Hangul can be written in: precomposed syllables (NFC),
+//! conjoining jamo (NFD), or the compatibility jamo that stand alone on
+//! a keyboard.
+//!
+//! Only Hangul is normalized; everything else is left as it is, which is
+//! all dero produces apart from the text it passes through.
+
+const SYLLABLES_START: u32 = 0xAC00;
+const SYLLABLES_END: u32 = 0xD7A3;
+const INITIAL_COUNT: u32 = 19;
+const VOWEL_COUNT: u32 = 21;
+const FINAL_COUNT: u32 = 28;
+/// Where the conjoining jamo that NFD uses start. The finals start one
+/// early, since a final of 0 means there is none.
+const CONJOINING_INITIALS: u32 = 0x1100;
+const CONJOINING_VOWELS: u32 = 0x1161;
+const CONJOINING_FINALS: u32 = 0x11A7;
+/// Where the compatibility vowels start; they are in the same order.
+const COMPATIBILITY_VOWELS: u32 = 0x314F;
+
+const COMPATIBILITY_INITIALS: [char; 19] = [
+    'ㄱ', 'ㄲ', 'ㄴ', 'ㄷ', 'ㄸ', 'ㄹ', 'ㅁ', 'ㅂ', 'ㅃ', 'ㅅ', 'ㅆ', 'ㅇ', 'ㅈ', 'ㅉ', 'ㅊ', 'ㅋ',
+    'ㅌ', 'ㅍ', 'ㅎ',
+];
+
+const COMPATIBILITY_FINALS: [char; 27] = [
+    'ㄱ', 'ㄲ', 'ㄳ', 'ㄴ', 'ㄵ', 'ㄶ', 'ㄷ', 'ㄹ', 'ㄺ', 'ㄻ', 'ㄼ', 'ㄽ', 'ㄾ', 'ㄿ', 'ㅀ', 'ㅁ',
+    'ㅂ', 'ㅄ', 'ㅅ', 'ㅆ', 'ㅇ', 'ㅈ', 'ㅊ', 'ㅋ', 'ㅌ', 'ㅍ', 'ㅎ',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    /// Precomposed syllables.
+    Nfc,
+    /// Conjoining jamo.
+    Nfd,
+    /// Compatibility jamo, one after another.
+    Compatibility,
+}
+
+impl Form {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Form::Nfc => "nfc",
+            Form::Nfd => "nfd",
+            Form::Compatibility => "compatibility",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Form> {
+        match name {
+            "nfc" => Some(Form::Nfc),
+            "nfd" => Some(Form::Nfd),
+            "compatibility" | "jamo" => Some(Form::Compatibility),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the character is a precomposed Hangul syllable.
+pub fn is_syllable(ch: char) -> bool {
+    let code = ch as u32;
+    code >= SYLLABLES_START && code <= SYLLABLES_END
+}
+
+/// The initial, vowel and final of a syllable, by index. A final of 0
+/// means there is none.
+pub fn split(ch: char) -> Option<(u32, u32, u32)> {
+    if !is_syllable(ch) {
+        return None;
+    }
+    let index = ch as u32 - SYLLABLES_START;
+    Some((index / (VOWEL_COUNT * FINAL_COUNT), (index / FINAL_COUNT) % VOWEL_COUNT,
+        index % FINAL_COUNT))
+}
+
+/// A syllable's jamo as compatibility jamo.
+pub fn compatibility_jamo(ch: char) -> Option<(char, char, Option<char>)> {
+    split(ch).map(|(initial, vowel, last)| (
+        COMPATIBILITY_INITIALS[initial as usize],
+        char_from(COMPATIBILITY_VOWELS + vowel),
+        if last > 0 { Some(COMPATIBILITY_FINALS[last as usize - 1]) } else { None },
+    ))
+}
+
+pub fn normalize(text: &str, form: Form) -> String {
+    match form {
+        Form::Nfc => compose(text),
+        Form::Nfd => decompose(text),
+        Form::Compatibility => to_compatibility(text),
+    }
+}
+
+/// Splits syllables into conjoining jamo.
+pub fn decompose(text: &str) -> String {
+    let mut decomposed = String::with_capacity(text.len() * 2);
+    for ch in text.chars() {
+        match split(ch) {
+            Some((initial, vowel, last)) => {
+                decomposed.push(char_from(CONJOINING_INITIALS + initial));
+                decomposed.push(char_from(CONJOINING_VOWELS + vowel));
+                if last > 0 {
+                    decomposed.push(char_from(CONJOINING_FINALS + last));
+                }
+            }
+            None => decomposed.push(ch),
+        }
+    }
+    decomposed
+}
+
+/// Joins conjoining jamo into syllables where they make one.
+pub fn compose(text: &str) -> String {
+    let mut composed = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let code = ch as u32;
+        let mut syllable = if within(code, CONJOINING_INITIALS, INITIAL_COUNT) {
+            match chars.peek().map(|&next| next as u32) {
+                Some(next) if within(next, CONJOINING_VOWELS, VOWEL_COUNT) => {
+                    chars.next();
+                    let initial = code - CONJOINING_INITIALS;
+                    let vowel = next - CONJOINING_VOWELS;
+                    SYLLABLES_START + (initial * VOWEL_COUNT + vowel) * FINAL_COUNT
+                }
+                _ => {
+                    composed.push(ch);
+                    continue;
+                }
+            }
+        } else if split(ch).map_or(false, |(_, _, last)| last == 0) {
+            code
+        } else {
+            composed.push(ch);
+            continue;
+        };
+        // A syllable without a final takes one that follows it.
+        match chars.peek().map(|&next| next as u32) {
+            Some(next) if within(next, CONJOINING_FINALS + 1, FINAL_COUNT - 1) => {
+                chars.next();
+                syllable += next - CONJOINING_FINALS;
+            }
+            _ => {}
+        }
+        composed.push(char_from(syllable));
+    }
+    composed
+}
+
+/// Spells syllables and conjoining jamo out in compatibility jamo.
+pub fn to_compatibility(text: &str) -> String {
+    let mut converted = String::with_capacity(text.len() * 2);
+    for ch in decompose(text).chars() {
+        let code = ch as u32;
+        if within(code, CONJOINING_INITIALS, INITIAL_COUNT) {
+            converted.push(COMPATIBILITY_INITIALS[(code - CONJOINING_INITIALS) as usize]);
+        } else if within(code, CONJOINING_VOWELS, VOWEL_COUNT) {
+            converted.push(char_from(COMPATIBILITY_VOWELS + code - CONJOINING_VOWELS));
+        } else if within(code, CONJOINING_FINALS + 1, FINAL_COUNT - 1) {
+            converted.push(COMPATIBILITY_FINALS[(code - CONJOINING_FINALS - 1) as usize]);
+        } else {
+            converted.push(ch);
+        }
+    }
+    converted
+}
+
+/// Whether a code is one of the `count` starting at `start`.
+fn within(code: u32, start: u32, count: u32) -> bool {
+    code >= start && code < start + count
+}
+
+/// Only called with codes in the Hangul blocks, which are all characters.
+fn char_from(code: u32) -> char {
+    ::std::char::from_u32(code).unwrap_or('\u{FFFD}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compatibility_jamo, compose, decompose, is_syllable, normalize, split, Form};
+
+    #[test]
+    fn splits_syllables_by_index() {
+        assert!(is_syllable('가') && is_syllable('힣') && !is_syllable('ㅎ'));
+        assert_eq!(split('가'), Some((0, 0, 0)));
+        assert_eq!(split('힣'), Some((18, 20, 27)));
+        assert_eq!(split('ㄱ'), None);
+        assert_eq!(compatibility_jamo('닭'), Some(('ㄷ', 'ㅏ', Some('ㄺ'))));
+    }
+
+    #[test]
+    fn decomposes_and_composes_back() {
+        let text = "한국어 text, 닭!";
+        let decomposed = decompose(text);
+        assert_eq!(decomposed.chars().filter(|&ch| split(ch).is_some()).count(), 0);
+        assert_eq!(compose(&decomposed), text);
+    }
+
+    #[test]
+    fn composes_a_syllable_with_a_trailing_final() {
+        assert_eq!(compose("가\u{11A8}"), "각");
+        assert_eq!(compose("각\u{11A8}"), "각\u{11A8}");
+        assert_eq!(compose("\u{1100}"), "\u{1100}");
+    }
+
+    #[test]
+    fn writes_compatibility_jamo() {
+        assert_eq!(normalize("한글", Form::Compatibility), "ㅎㅏㄴㄱㅡㄹ");
+        assert_eq!(normalize("a\u{1112}\u{1161}", Form::Compatibility), "aㅎㅏ");
+        assert_eq!(normalize(&decompose("한글"), Form::Nfc), "한글");
+    }
+}
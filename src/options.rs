@@ -0,0 +1,189 @@
+//! Command line options.
+
+use std::env;
+use std::path::PathBuf;
+
+use error::DerowinError;
+use DeroMode;
+
+pub const USAGE: &'static str = "\
+Usage: derowin [options] [default|input|lookup|romanize]
+       derowin [options] ctl COMMAND [ARGS]
+       derowin convert [convert options] FILE...  (see derowin convert --help)
+       derowin rules test [TEXT...]
+
+Options:
+    -v, --verbose       Log more (repeat for debug and trace output)
+    --log-file PATH     Also log to PATH, rotating it when it grows large
+    --log-text          Show typed and committed text in the log
+    --config PATH       Read the config from PATH instead of
+                        $XDG_CONFIG_HOME/derowin/config
+    --theme NAME        Use a built-in theme: auto, light, dark,
+                        solarized-light or solarized-dark
+    --on-top            Keep the window above other windows
+    --borderless        Open the window without decorations
+    --opacity N         Make the window see-through, from 0.0 to 1.0
+    --palette           Use a compact window that fits the text
+    --split             Show the romanized source above the converted text
+    --annotations       Show what each word was typed as over it
+    --annotate FORMAT   Commit words with what they were typed as, as html
+                        (<ruby> tags) or markdown ({한국|hangug})
+    --remember-geometry, --no-remember-geometry
+                        Open the window where it was last closed, or not
+    --no-tray           Don't show a tray icon (Linux)
+    --hotkey KEYS       Show and hide the window with a global shortcut,
+                        like ctrl+alt+k (Linux, X11)
+    --type              Type committed input into the window that had
+                        the focus, instead of copying it
+    --text TEXT         Start with TEXT in the buffer
+    --new-instance      Open a new window even if derowin is running
+                        (otherwise the mode and text go to that window)
+    -h, --help          Show this message
+
+Control commands, sent to the running window:
+    convert [TEXT]      Print TEXT (or stdin) converted to Hangul
+    insert [TEXT]       Type TEXT (or stdin) into the buffer
+    mode MODE           Switch to default, input, lookup or romanize mode
+    buffer              Print the converted buffer
+    show [MODE]         Bring the window to the front, switching modes
+    commit              Commit the buffer, and print what was committed
+    subscribe           Print everything that is committed from now on
+
+Environment:
+    DEROWIN_LOG         Log level when --verbose isn't given
+                        (off, error, warn, info, debug or trace)
+    DEROWIN_LOG_FILE    Log file when --log-file isn't given";
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The mode to start in.
+    pub mode: Option<DeroMode>,
+    /// The number of `-v` flags.
+    pub verbosity: usize,
+    pub log_file: Option<PathBuf>,
+    pub log_text: bool,
+    pub config: Option<PathBuf>,
+    /// Config values given as flags, which override the config file.
+    pub settings: Vec<(&'static str, String)>,
+    pub help: bool,
+    /// Text to start with in the buffer.
+    pub text: Option<String>,
+    /// The arguments after `ctl`, when run as a control client.
+    pub ctl: Option<Vec<String>>,
+    /// The arguments after `convert`, when converting files.
+    pub convert: Option<Vec<String>>,
+    /// The arguments after `rules`, when checking the rules file.
+    pub rules: Option<Vec<String>>,
+}
+
+impl Options {
+    /// Parses the arguments, not including the program name.
+    pub fn parse<I: IntoIterator<Item=String>>(args: I) -> Result<Options, DerowinError> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-v" | "--verbose" => options.verbosity += 1,
+                "-vv" => options.verbosity += 2,
+                "-vvv" => options.verbosity += 3,
+                "--log-file" => {
+                    let path = args.next().ok_or_else(|| missing_value(&arg))?;
+                    options.log_file = Some(PathBuf::from(path));
+                }
+                "--log-text" => options.log_text = true,
+                "--config" => {
+                    let path = args.next().ok_or_else(|| missing_value(&arg))?;
+                    options.config = Some(PathBuf::from(path));
+                }
+                "--theme" => {
+                    let theme = args.next().ok_or_else(|| missing_value(&arg))?;
+                    options.settings.push(("theme", theme));
+                }
+                "--on-top" => options.settings.push(("window.always-on-top", "on".to_string())),
+                "--borderless" => options.settings.push(("window.borderless", "on".to_string())),
+                "--opacity" => {
+                    let opacity = args.next().ok_or_else(|| missing_value(&arg))?;
+                    options.settings.push(("window.opacity", opacity));
+                }
+                "--palette" => options.settings.push(("window.layout", "palette".to_string())),
+                "--split" => options.settings.push(("window.split", "below".to_string())),
+                "--annotations" => {
+                    options.settings.push(("window.annotations", "on".to_string()));
+                }
+                "--annotate" => {
+                    let format = args.next().ok_or_else(|| missing_value(&arg))?;
+                    options.settings.push(("transform.annotate", format));
+                }
+                "--remember-geometry" => {
+                    options.settings.push(("window.remember-geometry", "on".to_string()));
+                }
+                "--no-remember-geometry" => {
+                    options.settings.push(("window.remember-geometry", "off".to_string()));
+                }
+                "--no-tray" => options.settings.push(("tray", "off".to_string())),
+                "--hotkey" => {
+                    let keys = args.next().ok_or_else(|| missing_value(&arg))?;
+                    options.settings.push(("hotkey", keys));
+                }
+                "--type" => options.settings.push(("output.input", "type".to_string())),
+                "--text" => {
+                    options.text = Some(args.next().ok_or_else(|| missing_value(&arg))?);
+                }
+                "--new-instance" => options.settings.push(("single-instance", "off".to_string())),
+                "-h" | "--help" => options.help = true,
+                "ctl" if options.mode.is_none() => {
+                    options.ctl = Some(args.by_ref().collect());
+                }
+                "convert" if options.mode.is_none() => {
+                    options.convert = Some(args.by_ref().collect());
+                }
+                "rules" if options.mode.is_none() => {
+                    options.rules = Some(args.by_ref().collect());
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(DerowinError::Usage(format!("Unknown option '{}'", arg)));
+                }
+                _ => match DeroMode::from_name(&arg) {
+                    Some(mode) if options.mode.is_none() => options.mode = Some(mode),
+                    _ => return Err(DerowinError::Usage(format!("Unexpected argument '{}'", arg))),
+                },
+            }
+        }
+        if options.log_file.is_none() {
+            options.log_file = env::var_os("DEROWIN_LOG_FILE")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from);
+        }
+        Ok(options)
+    }
+}
+
+fn missing_value(option: &str) -> DerowinError {
+    DerowinError::Usage(format!("Missing value for '{}'", option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+
+    fn settings(args: &[&str]) -> Vec<(&'static str, String)> {
+        Options::parse(args.iter().map(|arg| arg.to_string())).unwrap().settings
+    }
+
+    #[test]
+    fn sets_the_geometry_and_annotation_options() {
+        assert_eq!(settings(&["--no-remember-geometry", "--annotations"]), vec![
+            ("window.remember-geometry", "off".to_string()),
+            ("window.annotations", "on".to_string()),
+        ]);
+        assert_eq!(settings(&["--remember-geometry", "--annotate", "html"]), vec![
+            ("window.remember-geometry", "on".to_string()),
+            ("transform.annotate", "html".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn needs_a_format_to_annotate() {
+        assert!(Options::parse(vec!["--annotate".to_string()]).is_err());
+    }
+}
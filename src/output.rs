@@ -0,0 +1,193 @@
+//! Where committed text goes.
+//!
+//! Each mode has its own output, set with `output.default`, `output.input`,
+//! `output.lookup` and `output.romanize`:
+//!
+//! - `clipboard` (the default): copy the text, to be pasted by hand
+//! - `type`: hide the window, so that the window that had the focus before
+//!   gets it back, and type the text into it (see `typing` for the
+//!   backends, and `output.type-delay` for how long to wait in between)
+
+use std::thread;
+use std::time::Duration;
+
+use clipboard::Backend;
+use config::Config;
+use error::DerowinError;
+use typing::Typer;
+use DeroMode;
+
+/// Milliseconds to wait for the focus to move after hiding the window.
+const DEFAULT_TYPE_DELAY: u64 = 150;
+
+#[derive(Debug, Clone)]
+pub enum Sink {
+    Clipboard(Backend),
+    /// Types the text after waiting for the given milliseconds.
+    Type(Typer, u64),
+    /// Keeps the text, for tests.
+    #[cfg(test)]
+    Memory(Vec<String>),
+}
+
+impl Sink {
+    pub fn send(&mut self, text: &str) -> Result<(), DerowinError> {
+        match *self {
+            Sink::Clipboard(backend) => backend.copy(text),
+            Sink::Type(typer, delay) => {
+                thread::sleep(Duration::from_millis(delay));
+                typer.type_text(text)
+            }
+            #[cfg(test)]
+            Sink::Memory(ref mut sent) => {
+                sent.push(text.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the window has to get out of the way before sending.
+    pub fn types(&self) -> bool {
+        match *self {
+            Sink::Type(..) => true,
+            _ => false,
+        }
+    }
+
+    /// A short description for the status bar.
+    pub fn describe(&self) -> String {
+        match *self {
+            Sink::Clipboard(Backend::Unavailable) => String::from("no clipboard"),
+            Sink::Clipboard(backend) => backend.name().to_string(),
+            Sink::Type(typer, _) => format!("types with {}", typer.name()),
+            #[cfg(test)]
+            Sink::Memory(_) => String::from("memory"),
+        }
+    }
+}
+
+/// The output of each mode.
+#[derive(Debug, Clone)]
+pub struct Outputs {
+    default: Sink,
+    input: Sink,
+    lookup: Sink,
+    romanize: Sink,
+}
+
+impl Outputs {
+    pub fn from_config(config: &Config, clipboard: Backend) -> Result<Outputs, DerowinError> {
+        let delay = config.parse_value("output.type-delay")?.unwrap_or(DEFAULT_TYPE_DELAY);
+        let typer = match config.parse_with("output.typer", Typer::from_name)? {
+            Some(typer) => typer,
+            None => Typer::detect(),
+        };
+        let sink = |mode: DeroMode| -> Result<Sink, DerowinError> {
+            let key = format!("output.{}", mode.name());
+            let types = config.parse_with(&key, |value| match value {
+                "clipboard" => Some(false),
+                "type" => Some(true),
+                _ => None,
+            })?;
+            match (types, typer) {
+                (Some(true), Some(typer)) => {
+                    info!("{} mode types with {}", mode.label(), typer.name());
+                    Ok(Sink::Type(typer, delay))
+                }
+                (Some(true), None) => {
+                    warn!("No way to type text was found; {} mode copies instead", mode.label());
+                    Ok(Sink::Clipboard(clipboard))
+                }
+                _ => Ok(Sink::Clipboard(clipboard)),
+            }
+        };
+        Ok(Outputs {
+            default: sink(DeroMode::Default)?,
+            input: sink(DeroMode::Input)?,
+            lookup: sink(DeroMode::Lookup)?,
+            romanize: sink(DeroMode::Romanize)?,
+        })
+    }
+
+    pub fn get(&self, mode: DeroMode) -> &Sink {
+        match mode {
+            DeroMode::Default => &self.default,
+            DeroMode::Input => &self.input,
+            DeroMode::Lookup => &self.lookup,
+            DeroMode::Romanize => &self.romanize,
+        }
+    }
+
+    pub fn get_mut(&mut self, mode: DeroMode) -> &mut Sink {
+        match mode {
+            DeroMode::Default => &mut self.default,
+            DeroMode::Input => &mut self.input,
+            DeroMode::Lookup => &mut self.lookup,
+            DeroMode::Romanize => &mut self.romanize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clipboard::Backend;
+    use compose::Converter;
+    use config::Config;
+    use romanize::Style;
+    use transform::Transforms;
+    use DeroMode;
+
+    use super::{Outputs, Sink};
+
+    fn sent(sink: &Sink) -> &[String] {
+        match *sink {
+            Sink::Memory(ref sent) => sent,
+            _ => panic!("not a memory sink"),
+        }
+    }
+
+    /// Outputs that keep everything committed in every mode.
+    fn memory() -> Outputs {
+        Outputs {
+            default: Sink::Memory(Vec::new()),
+            input: Sink::Memory(Vec::new()),
+            lookup: Sink::Memory(Vec::new()),
+            romanize: Sink::Memory(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn sends_text_as_it_is() {
+        let mut outputs = memory();
+        let sink = outputs.get_mut(DeroMode::Input);
+        sink.send("한국어\n").unwrap();
+        sink.send("𝄞 😀").unwrap();
+        assert_eq!(sent(sink), &[String::from("한국어\n"), String::from("𝄞 😀")]);
+        assert!(!sink.types());
+        assert!(sent(outputs.get(DeroMode::Default)).is_empty());
+    }
+
+    #[test]
+    fn commits_the_transformed_text() {
+        let config = Config::parse("transform.input.template = [{text}]\n", "config").unwrap();
+        let transforms = Transforms::from_config(&config).unwrap();
+        let converter = Converter::new();
+        let source = "hangug\n⟦𝄞⟧";
+        let converted = converter.convert(source);
+        let (text, _) = transforms.get(DeroMode::Input)
+            .apply(&converter, &converted, source, DeroMode::Input, Style::Dero)
+            .unwrap();
+        let mut outputs = memory();
+        outputs.get_mut(DeroMode::Input).send(&text).unwrap();
+        let expected = format!("[{}]", converted);
+        assert!(expected.contains("\n𝄞"));
+        assert_eq!(sent(outputs.get(DeroMode::Input)), &[expected]);
+    }
+
+    #[test]
+    fn copies_by_default() {
+        let outputs = Outputs::from_config(&Config::default(), Backend::Unavailable).unwrap();
+        assert!(!outputs.get(DeroMode::Default).types());
+        assert_eq!(outputs.get(DeroMode::Romanize).describe(), "no clipboard");
+    }
+}
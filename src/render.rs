@@ -0,0 +1,440 @@
+//! Drawing text and the caret.
+
+use rsdl2::{Rect, Renderer};
+use rsdl2_font;
+use rsdl2_font::rusttype::{Font, Scale};
+
+use annotate::Segment;
+use error::DerowinError;
+use theme::{CaretShape, Color, Theme};
+
+pub const FONT_POINT_SIZE: f32 = 18.0;
+/// The size of the romanization over words in the annotated preview.
+pub const NOTE_POINT_SIZE: f32 = 10.0;
+
+/// Vertical measurements of the font, in pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Metrics {
+    /// The height of a line of glyphs.
+    pub height: i32,
+    /// The distance between the tops of two lines.
+    pub line_skip: i32,
+}
+
+impl Metrics {
+    pub fn of(font: &Font) -> Metrics {
+        let v_metrics = font.v_metrics(Scale::uniform(FONT_POINT_SIZE));
+        let line_skip = if v_metrics.line_gap != 0.0 {
+            v_metrics.line_gap.ceil() as i32
+        } else {
+            (v_metrics.ascent * 1.25).ceil() as i32
+        };
+        Metrics {
+            height: (v_metrics.ascent - v_metrics.descent).ceil() as i32,
+            line_skip: line_skip,
+        }
+    }
+}
+
+/// The width in pixels of a line of text.
+pub fn text_width(font: &Font, text: &str) -> i32 {
+    text_width_at(font, text, FONT_POINT_SIZE)
+}
+
+/// The width in pixels of a line of text at the given point size.
+pub fn text_width_at(font: &Font, text: &str, size: f32) -> i32 {
+    let scale = Scale::uniform(size);
+    let mut width = 0.0;
+    let mut last = None;
+    for ch in text.chars() {
+        if let Some(last) = last {
+            width += font.pair_kerning(scale, last, ch);
+        }
+        if let Some(glyph) = font.glyph(ch) {
+            width += glyph.scaled(scale).h_metrics().advance_width;
+        }
+        last = Some(ch);
+    }
+    width.ceil() as i32
+}
+
+/// Draws a single line of text, returning its width.
+pub fn draw_text(renderer: &Renderer, font: &Font, text: &str, color: Color, x: i32, y: i32)
+    -> Result<i32, DerowinError>
+{
+    draw_text_at(renderer, font, text, color, FONT_POINT_SIZE, x, y)
+}
+
+/// Draws a single line of text at the given point size, returning its
+/// width.
+pub fn draw_text_at(renderer: &Renderer, font: &Font, text: &str, color: Color, size: f32,
+    x: i32, y: i32)
+    -> Result<i32, DerowinError>
+{
+    if text.is_empty() {
+        return Ok(0);
+    }
+    let surf = rsdl2_font::render(font, text, color, size)
+        .map_err(|e| DerowinError::Render(format!("{:?}", e)))?;
+    let tex = renderer.create_texture_from_surface(&surf)
+        .map_err(|e| DerowinError::Render(format!("{:?}", e)))?;
+    let texpos = tex.rect_at(x, y);
+    renderer.copy(&tex, None, Some(texpos))
+        .map_err(|e| DerowinError::Render(format!("{:?}", e)))?;
+    Ok(text_width_at(font, text, size))
+}
+
+/// Draws a line of converted text, with romanization that dero left alone
+/// in the theme's unconverted color. `literal` are the runs of characters
+/// in the line that are kept literal, which are drawn as they are.
+pub fn draw_converted(renderer: &Renderer, font: &Font, theme: &Theme, line: &str,
+    literal: &[(usize, usize)], x: i32, y: i32)
+    -> Result<(), DerowinError>
+{
+    let mut x = x;
+    for (run, unconverted) in runs(line, literal) {
+        let color = if unconverted { theme.unconverted } else { theme.foreground };
+        x += draw_text(renderer, font, run, color, x, y)?;
+    }
+    Ok(())
+}
+
+/// Splits text into runs of unconverted Latin letters and everything else.
+/// Letters in the `literal` runs of characters were kept on purpose.
+fn runs<'a>(line: &'a str, literal: &[(usize, usize)]) -> Vec<(&'a str, bool)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut current = None;
+    for (index, (i, ch)) in line.char_indices().enumerate() {
+        let kept = literal.iter().any(|&(start, end)| index >= start && index < end);
+        let unconverted = ch.is_ascii_alphabetic() && !kept;
+        match current {
+            Some(kind) if kind != unconverted => {
+                runs.push((&line[start..i], kind));
+                start = i;
+            }
+            _ => {}
+        }
+        current = Some(unconverted);
+    }
+    if let Some(kind) = current {
+        runs.push((&line[start..], kind));
+    }
+    runs
+}
+
+pub fn fill_rect(renderer: &Renderer, color: Color, x: i32, y: i32, w: i32, h: i32)
+    -> Result<(), DerowinError>
+{
+    let rect = Rect::new(x, y, w.max(0) as u32, h.max(0) as u32);
+    renderer.color(color).fill_rect(rect)
+        .map_err(|e| DerowinError::Render(format!("{:?}", e)))
+}
+
+/// Draws the caret at the top left corner of the character it is before.
+/// `next` is the character after the caret, which sets the width of block
+/// and underline carets.
+pub fn draw_caret(renderer: &Renderer, font: &Font, theme: &Theme, shape: CaretShape,
+    next: Option<char>, x: i32, y: i32)
+    -> Result<(), DerowinError>
+{
+    let metrics = Metrics::of(font);
+    let width = match next {
+        Some(ch) if ch != '\n' => text_width(font, &ch.to_string()),
+        _ => (FONT_POINT_SIZE / 2.0).ceil() as i32,
+    };
+    match shape {
+        CaretShape::Bar => fill_rect(renderer, theme.caret, x, y, 2, metrics.height),
+        CaretShape::Block => fill_rect(renderer, theme.caret, x, y, width, metrics.height),
+        CaretShape::Underline => {
+            fill_rect(renderer, theme.caret, x, y + metrics.height - 2, width, 2)
+        }
+    }
+}
+
+/// What to draw of the buffer.
+#[derive(Debug, Clone)]
+pub struct BufferView<'a> {
+    /// The converted text before and after the caret.
+    pub before: &'a str,
+    pub after: &'a str,
+    /// What an input method is composing, shown underlined at the caret.
+    pub preedit: &'a str,
+    /// The selected characters of `before` and `after` together, by
+    /// character index.
+    pub selection: Option<(usize, usize)>,
+    /// Characters to underline, the same way, to show what they match in
+    /// the other pane.
+    pub marked: Option<(usize, usize)>,
+    /// Runs of characters, the same way, that are kept literal.
+    pub literal: Vec<(usize, usize)>,
+    /// Whether the text is converted, so that romanization left in it
+    /// stands out. The source pane isn't.
+    pub converted: bool,
+    pub show_caret: bool,
+    /// Whether the caret is in literal text, which gives it another shape.
+    pub literal_caret: bool,
+    /// How many lines are scrolled out of view at the top.
+    pub scroll: usize,
+    /// The right and bottom edges of the pane. Text that would go past
+    /// them isn't drawn, nor are lines that don't fit whole.
+    pub clip: (i32, i32),
+}
+
+/// Draws the converted buffer, split at the caret, returning where the
+/// caret was put.
+pub fn draw_buffer(renderer: &Renderer, font: &Font, theme: &Theme, view: &BufferView,
+    x: i32, y: i32)
+    -> Result<(i32, i32), DerowinError>
+{
+    let metrics = Metrics::of(font);
+    let scroll = view.scroll as i32;
+    // The preedit stays on the caret's line.
+    let preedit = view.preedit.replace('\n', " ");
+    let caret_line = view.before.matches('\n').count() as i32;
+    let preedit_x = x + text_width(font, view.before.rsplit('\n').next().unwrap_or(""));
+    let caret_x = preedit_x + text_width(font, &preedit);
+    let caret_y = y + (caret_line - scroll) * metrics.line_skip;
+    let mut text = String::with_capacity(view.before.len() + preedit.len() + view.after.len());
+    text.push_str(view.before);
+    text.push_str(&preedit);
+    text.push_str(view.after);
+    // The selection and the caret go under the text, so that they don't
+    // hide it.
+    let split = view.before.chars().count();
+    let shift = |i: usize| if i > split { i + preedit.chars().count() } else { i };
+    let literal = view.literal.iter()
+        .map(|&(start, end)| (shift(start), shift(end)))
+        .collect::<Vec<_>>();
+    let spans = Spans { text: &text, scroll: view.scroll, clip: view.clip, x: x, y: y };
+    for &span in &literal {
+        spans.draw(renderer, font, theme.literal, false, span)?;
+    }
+    if let Some((start, end)) = view.selection {
+        spans.draw(renderer, font, theme.selection, false, (shift(start), shift(end)))?;
+    }
+    if let Some((start, end)) = view.marked {
+        spans.draw(renderer, font, theme.caret, true, (shift(start), shift(end)))?;
+    }
+    let caret_shown = caret_line >= scroll && caret_y + metrics.height <= view.clip.1;
+    if view.show_caret && caret_shown && caret_x < view.clip.0 {
+        let shape = if view.literal_caret { theme.literal_caret_shape } else { theme.caret_shape };
+        draw_caret(renderer, font, theme, shape, view.after.chars().next(), caret_x, caret_y)?;
+    }
+    let mut line_start = 0;
+    for (i, line) in text.split('\n').enumerate() {
+        let start = line_start;
+        line_start += line.chars().count() + 1;
+        if i < view.scroll {
+            continue;
+        }
+        let line_y = y + (i as i32 - scroll) * metrics.line_skip;
+        if line_y + metrics.height > view.clip.1 {
+            break;
+        }
+        let line = fitting(font, line, view.clip.0 - x);
+        if view.converted {
+            let line_literal = within_line(&literal, start, line.chars().count());
+            draw_converted(renderer, font, theme, line, &line_literal, x, line_y)?;
+        } else {
+            draw_text(renderer, font, line, theme.foreground, x, line_y)?;
+        }
+    }
+    if !preedit.is_empty() && caret_shown {
+        fill_rect(renderer, theme.foreground, preedit_x, caret_y + metrics.height - 1,
+            caret_x.min(view.clip.0) - preedit_x, 1)?;
+    }
+    Ok((caret_x, caret_y))
+}
+
+/// The runs of characters that are in the line starting at `start`, made
+/// relative to it.
+fn within_line(spans: &[(usize, usize)], start: usize, length: usize) -> Vec<(usize, usize)> {
+    spans.iter()
+        .filter(|&&(from, to)| from < start + length && to > start)
+        .map(|&(from, to)| (from.saturating_sub(start), (to - start).min(length)))
+        .collect()
+}
+
+/// Draws annotated words with their notes small and centered over them,
+/// skipping the first `scroll` lines, and leaving out words that go past
+/// the right and bottom edges in `clip`. `literal` are the runs of
+/// characters of the words, by index in them all, that are kept literal.
+/// Returns where the text ends.
+pub fn draw_annotated(renderer: &Renderer, font: &Font, theme: &Theme, segments: &[Segment],
+    literal: &[(usize, usize)], scroll: usize, clip: (i32, i32), x: i32, y: i32)
+    -> Result<(i32, i32), DerowinError>
+{
+    let metrics = Metrics::of(font);
+    let note_metrics = font.v_metrics(Scale::uniform(NOTE_POINT_SIZE));
+    let note_height = (note_metrics.ascent - note_metrics.descent).ceil() as i32;
+    let line_skip = note_height + metrics.line_skip;
+    let mut line = 0;
+    let mut left = x;
+    let top = |line: usize| y + (line as i32 - scroll as i32) * line_skip + note_height;
+    let shown = |line: usize, right: i32| {
+        line >= scroll && right <= clip.0 && top(line) + metrics.height <= clip.1
+    };
+    // Where each part of a word starts, by character index.
+    let mut start = 0;
+    for segment in segments {
+        // Spaces may hold line breaks.
+        let mut parts = segment.base.split('\n');
+        let first = parts.next().unwrap_or("");
+        let width = text_width(font, first);
+        let first_literal = within_line(literal, start, first.chars().count());
+        start += first.chars().count() + 1;
+        match segment.note {
+            Some(ref note) => {
+                let note_width = text_width_at(font, note, NOTE_POINT_SIZE);
+                let column = width.max(note_width);
+                if shown(line, left + column) {
+                    draw_text_at(renderer, font, note, theme.annotation, NOTE_POINT_SIZE,
+                        left + (column - note_width) / 2, top(line) - note_height)?;
+                    draw_converted(renderer, font, theme, first, &first_literal,
+                        left + (column - width) / 2, top(line))?;
+                }
+                left += column;
+            }
+            None => {
+                if shown(line, left + width) {
+                    draw_converted(renderer, font, theme, first, &first_literal, left,
+                        top(line))?;
+                }
+                left += width;
+            }
+        }
+        for part in parts {
+            line += 1;
+            left = x;
+            let width = text_width(font, part);
+            if shown(line, left + width) {
+                let part_literal = within_line(literal, start, part.chars().count());
+                draw_converted(renderer, font, theme, part, &part_literal, left, top(line))?;
+            }
+            start += part.chars().count() + 1;
+            left += width;
+        }
+        // The split counted a newline after the last part.
+        start -= 1;
+    }
+    Ok((left, top(line)))
+}
+
+/// Text drawn as it is in a buffer view, to draw spans of it.
+struct Spans<'a> {
+    text: &'a str,
+    scroll: usize,
+    clip: (i32, i32),
+    x: i32,
+    y: i32,
+}
+
+impl<'a> Spans<'a> {
+    /// Highlights a span of characters line by line, or underlines them.
+    fn draw(&self, renderer: &Renderer, font: &Font, color: Color, underline: bool,
+        span: (usize, usize))
+        -> Result<(), DerowinError>
+    {
+        let metrics = Metrics::of(font);
+        let newline_width = (FONT_POINT_SIZE / 2.0).ceil() as i32;
+        let (start, end) = span;
+        let mut line_start = 0;
+        for (i, line) in self.text.split('\n').enumerate() {
+            let length = line.chars().count();
+            let line_end = line_start + length;
+            let top = self.y + (i as i32 - self.scroll as i32) * metrics.line_skip;
+            if top + metrics.height > self.clip.1 {
+                break;
+            }
+            if i >= self.scroll && start <= line_end && end > line_start {
+                let from = start.saturating_sub(line_start);
+                let to = (end - line_start).min(length);
+                let left = self.x + text_width(font, char_prefix(line, from));
+                let mut right = self.x + text_width(font, char_prefix(line, to));
+                // The newline at the end is selected too.
+                if end > line_end && !underline {
+                    right += newline_width;
+                }
+                let right = right.min(self.clip.0);
+                let (top, height) = if underline {
+                    (top + metrics.height - 2, 2)
+                } else {
+                    (top, metrics.height)
+                };
+                if left < right {
+                    fill_rect(renderer, color, left, top, right - left, height)?;
+                }
+            }
+            line_start = line_end + 1;
+        }
+        Ok(())
+    }
+}
+
+/// The start of a line that fits in a width.
+fn fitting<'a>(font: &Font, line: &'a str, width: i32) -> &'a str {
+    if text_width(font, line) <= width {
+        return line;
+    }
+    // The longest beginning that fits, found by halving.
+    let (mut low, mut high) = (0, line.chars().count());
+    while low < high {
+        let middle = (low + high + 1) / 2;
+        if text_width(font, char_prefix(line, middle)) <= width {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    char_prefix(line, low)
+}
+
+/// The character boundary nearest to `x` on the given line of the text,
+/// drawn from `left`, by character index in the whole text. Points past
+/// the last line find the end.
+pub fn char_at(font: &Font, text: &str, line: usize, x: i32, left: i32) -> usize {
+    let mut index = 0;
+    for (i, line_text) in text.split('\n').enumerate() {
+        let length = line_text.chars().count();
+        if i == line {
+            let nearest = (0..length + 1)
+                .min_by_key(|&n| (left + text_width(font, char_prefix(line_text, n)) - x).abs())
+                .unwrap_or(0);
+            return index + nearest;
+        }
+        index += length + 1;
+    }
+    text.chars().count()
+}
+
+/// The first `count` characters of the text.
+pub fn char_prefix(text: &str, count: usize) -> &str {
+    match text.char_indices().nth(count) {
+        Some((i, _)) => &text[..i],
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{runs, within_line};
+
+    #[test]
+    fn colors_letters_left_unconverted() {
+        assert_eq!(runs("한국 xq!", &[]), vec![("한국 ", false), ("xq", true), ("!", false)]);
+    }
+
+    #[test]
+    fn keeps_literal_letters_plain() {
+        assert_eq!(runs("한 ⟦abc⟧ d", &[(2, 7)]), vec![("한 ⟦abc⟧ ", false), ("d", true)]);
+    }
+
+    #[test]
+    fn makes_spans_relative_to_the_line() {
+        let spans = [(1, 3), (5, 12), (20, 21)];
+        assert_eq!(within_line(&spans, 4, 4), vec![(1, 4)]);
+        assert_eq!(within_line(&spans, 0, 4), vec![(1, 3)]);
+        assert_eq!(within_line(&spans, 13, 4), vec![]);
+    }
+}
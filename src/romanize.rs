@@ -0,0 +1,362 @@
+//! Hangul to romanization, the other way round from dero.
+//!
+//! The `dero` style spells each jamo the way dero reads it, so that the
+//! result converts back to the same Hangul. It puts a `-` between
+//! syllables where they would otherwise be read differently (`hang-ug`,
+//! not `hangug`). The Revised Romanization and McCune–Reischauer styles
+//! spell the text the way it sounds instead, following the main sound
+//! change rules: a final consonant carries over to a following vowel, and
+//! consonants next to each other assimilate.
+//!
+//! Anything that isn't a Hangul syllable is left as it is.
+
+use normalize;
+
+/// Put between syllables in the dero style where they would run together.
+const SYLLABLE_BREAK: char = '-';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Dero,
+    Revised,
+    McCuneReischauer,
+}
+
+impl Style {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Style::Dero => "dero",
+            Style::Revised => "revised",
+            Style::McCuneReischauer => "mccune-reischauer",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Style> {
+        match name {
+            "dero" => Some(Style::Dero),
+            "revised" | "rr" => Some(Style::Revised),
+            "mccune-reischauer" | "mr" => Some(Style::McCuneReischauer),
+            _ => None,
+        }
+    }
+}
+
+/// The consonants, in the order of the initials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Consonant {
+    G, Kk, N, D, Tt, R, M, B, Pp, S, Ss, Ng, J, Jj, Ch, K, T, P, H,
+}
+
+use self::Consonant::*;
+
+const INITIALS: [Consonant; 19] = [G, Kk, N, D, Tt, R, M, B, Pp, S, Ss, Ng, J, Jj, Ch, K, T, P, H];
+
+/// The finals, as one or two consonants.
+const FINALS: [&'static [Consonant]; 28] = [
+    &[], &[G], &[Kk], &[G, S], &[N], &[N, J], &[N, H], &[D], &[R], &[R, G], &[R, M], &[R, B],
+    &[R, S], &[R, T], &[R, P], &[R, H], &[M], &[B], &[B, S], &[S], &[Ss], &[Ng], &[J], &[Ch],
+    &[K], &[T], &[P], &[H],
+];
+
+const DERO_VOWELS: [&'static str; 21] = [
+    "a", "ae", "ya", "yae", "eo", "e", "yeo", "ye", "o", "wa", "wae", "oe", "yo", "u", "wo",
+    "we", "wi", "yu", "eu", "ui", "i",
+];
+
+const MR_VOWELS: [&'static str; 21] = [
+    "a", "ae", "ya", "yae", "ŏ", "e", "yŏ", "ye", "o", "wa", "wae", "oe", "yo", "u", "wŏ",
+    "we", "wi", "yu", "ŭ", "ŭi", "i",
+];
+
+/// A syllable split into its jamo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Syllable {
+    initial: Consonant,
+    vowel: usize,
+    /// What is pronounced at the end, which sound changes may alter.
+    last: Vec<Consonant>,
+}
+
+fn split(ch: char) -> Option<Syllable> {
+    normalize::split(ch).map(|(initial, vowel, last)| Syllable {
+        initial: INITIALS[initial as usize],
+        vowel: vowel as usize,
+        last: FINALS[last as usize].to_vec(),
+    })
+}
+
+/// What is typed in dero for a syllable's initial, vowel and final, if it
+/// is one. A silent initial is typed as nothing.
+pub fn dero_keys(ch: char) -> Option<(&'static str, &'static str, Option<String>)> {
+    split(ch).map(|syllable| {
+        let last = syllable.last.iter()
+            .map(|&consonant| final_spelling(consonant, Style::Dero))
+            .collect::<String>();
+        (initial_spelling(syllable.initial, None, Style::Dero), DERO_VOWELS[syllable.vowel],
+            if last.is_empty() { None } else { Some(last) })
+    })
+}
+
+/// Romanizes the Hangul in the text.
+pub fn romanize(text: &str, style: Style) -> String {
+    let mut romanized = String::with_capacity(text.len() * 2);
+    let mut word = Vec::new();
+    for ch in text.chars() {
+        match split(ch) {
+            Some(syllable) => word.push(syllable),
+            None => {
+                romanize_word(&mut word, style, &mut romanized);
+                romanized.push(ch);
+            }
+        }
+    }
+    romanize_word(&mut word, style, &mut romanized);
+    romanized
+}
+
+/// Romanizes a run of syllables, emptying it.
+fn romanize_word(word: &mut Vec<Syllable>, style: Style, out: &mut String) {
+    if style != Style::Dero {
+        for i in 1..word.len() {
+            let (before, after) = word.split_at_mut(i);
+            change_sounds(&mut before[i - 1], &mut after[0]);
+        }
+        if let Some(last) = word.last_mut() {
+            last.last = neutralize(&last.last).into_iter().collect();
+        }
+    }
+    for i in 0..word.len() {
+        let syllable = &word[i];
+        let previous = if i > 0 { Some(&word[i - 1]) } else { None };
+        let initial = initial_spelling(syllable.initial, previous, style);
+        if style == Style::Dero {
+            if let Some(previous) = previous {
+                if runs_together(previous, syllable) {
+                    out.push(SYLLABLE_BREAK);
+                }
+            }
+        } else if style == Style::McCuneReischauer
+            && previous.map_or(false, |p| p.last == [N]) && syllable.initial == G
+        {
+            // Otherwise it would read as ng.
+            out.push('\'');
+        }
+        out.push_str(initial);
+        out.push_str(match style {
+            Style::McCuneReischauer => MR_VOWELS[syllable.vowel],
+            _ => DERO_VOWELS[syllable.vowel],
+        });
+        for &consonant in &syllable.last {
+            out.push_str(final_spelling(consonant, style));
+        }
+    }
+    word.clear();
+}
+
+/// Applies the sound changes between two syllables, for the styles that
+/// spell what is heard.
+fn change_sounds(first: &mut Syllable, second: &mut Syllable) {
+    // A final carries over to a vowel, leaving the first of two behind.
+    // A final h goes quiet instead.
+    if second.initial == Ng && !first.last.is_empty() && first.last != [Ng] {
+        let moved = first.last.pop().unwrap();
+        if moved != H {
+            second.initial = moved;
+        }
+    }
+    // h next to a plain stop makes it aspirated.
+    if first.last.last() == Some(&H) {
+        if let Some(aspirated) = aspirate(second.initial) {
+            first.last.pop();
+            second.initial = aspirated;
+        }
+    }
+    if second.initial == H {
+        if let Some(aspirated) = first.last.last().and_then(|&last| aspirate(last)) {
+            first.last.pop();
+            second.initial = aspirated;
+        }
+    }
+    // The final is then said as one of seven sounds.
+    first.last = neutralize(&first.last).into_iter().collect();
+    let last = match first.last.first() {
+        Some(&last) => last,
+        None => return,
+    };
+    match (last, second.initial) {
+        (N, R) | (R, N) => {
+            first.last = vec![R];
+            second.initial = R;
+        }
+        (M, R) | (Ng, R) => second.initial = N,
+        (G, R) | (D, R) | (B, R) => {
+            second.initial = N;
+            first.last = vec![nasalize(last)];
+        }
+        (G, N) | (G, M) | (D, N) | (D, M) | (B, N) | (B, M) => {
+            first.last = vec![nasalize(last)];
+        }
+        _ => {}
+    }
+}
+
+fn aspirate(consonant: Consonant) -> Option<Consonant> {
+    match consonant {
+        G => Some(K),
+        D => Some(T),
+        B => Some(P),
+        J => Some(Ch),
+        _ => None,
+    }
+}
+
+fn nasalize(consonant: Consonant) -> Consonant {
+    match consonant {
+        G => Ng,
+        D => N,
+        B => M,
+        other => other,
+    }
+}
+
+/// The one sound a final is said as at the end of a syllable.
+fn neutralize(last: &[Consonant]) -> Option<Consonant> {
+    let sound = match *last {
+        [] => return None,
+        [R, G] => G,
+        [R, M] => M,
+        [R, P] => B,
+        [first, _] => first,
+        [single] => single,
+        _ => return None,
+    };
+    Some(match sound {
+        G | Kk | K => G,
+        N => N,
+        D | S | Ss | J | Ch | T | H => D,
+        R => R,
+        M => M,
+        B | Pp | P => B,
+        Ng => Ng,
+        Tt | Jj => D,
+    })
+}
+
+fn initial_spelling(consonant: Consonant, previous: Option<&Syllable>, style: Style)
+    -> &'static str
+{
+    match style {
+        Style::Dero | Style::Revised => match consonant {
+            G => "g", Kk => "kk", N => "n", D => "d", Tt => "tt",
+            // An l after an l is written as one.
+            R if style == Style::Revised && previous.map_or(false, |p| p.last == [R]) => "l",
+            R => "r", M => "m", B => "b", Pp => "pp", S => "s", Ss => "ss", Ng => "",
+            J => "j", Jj => "jj", Ch => "ch", K => "k", T => "t", P => "p", H => "h",
+        },
+        Style::McCuneReischauer => {
+            // Plain stops are voiced between voiced sounds.
+            let voiced = previous.map_or(false, |p| match p.last.last() {
+                None | Some(&N) | Some(&R) | Some(&M) | Some(&Ng) => true,
+                _ => false,
+            });
+            match consonant {
+                G if voiced => "g", G => "k",
+                D if voiced => "d", D => "t",
+                B if voiced => "b", B => "p",
+                J if voiced => "j", J => "ch",
+                R if previous.map_or(false, |p| p.last == [R]) => "l",
+                R => "r",
+                Kk => "kk", N => "n", Tt => "tt", M => "m", Pp => "pp", S => "s", Ss => "ss",
+                Ng => "", Jj => "tch", Ch => "ch'", K => "k'", T => "t'", P => "p'", H => "h",
+            }
+        }
+    }
+}
+
+fn final_spelling(consonant: Consonant, style: Style) -> &'static str {
+    match style {
+        Style::Dero => match consonant {
+            G => "g", Kk => "kk", N => "n", D => "d", R => "l", M => "m", B => "b", S => "s",
+            Ss => "ss", Ng => "ng", J => "j", Ch => "ch", K => "k", T => "t", P => "p", H => "h",
+            Tt => "tt", Pp => "pp", Jj => "jj",
+        },
+        // Finals have been neutralized by now.
+        Style::Revised | Style::McCuneReischauer => match consonant {
+            G => "k", N => "n", D => "t", R => "l", M => "m", B => "p", Ng => "ng",
+            _ => "",
+        },
+    }
+}
+
+/// Whether dero would read the boundary between two syllables somewhere
+/// else: a final before a vowel, vowels that make another vowel together,
+/// or a consonant that doubles into a tense one.
+fn runs_together(first: &Syllable, second: &Syllable) -> bool {
+    let ends_in_vowel = first.last.is_empty();
+    if second.initial == Ng {
+        if !ends_in_vowel {
+            return true;
+        }
+        let last = DERO_VOWELS[first.vowel].chars().last();
+        let next = DERO_VOWELS[second.vowel].chars().next();
+        return match (last, next) {
+            (Some('a'), Some('e')) | (Some('e'), Some('o')) | (Some('e'), Some('u'))
+                | (Some('u'), Some('i')) | (Some('o'), Some('e')) => true,
+            _ => false,
+        };
+    }
+    match (first.last.last(), second.initial) {
+        (Some(&S), S) | (Some(&S), Ss) | (Some(&Ss), S) | (Some(&Ss), Ss)
+            | (Some(&K), K) | (Some(&K), Kk) | (Some(&Kk), K) | (Some(&Kk), Kk)
+            | (Some(&J), J) | (Some(&J), Jj) | (Some(&T), T) | (Some(&T), Tt)
+            | (Some(&P), P) | (Some(&P), Pp) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dero::deromanize_escaped;
+
+    use super::{dero_keys, romanize, Style};
+
+    #[test]
+    fn dero_style_breaks_syllables_that_would_run_together() {
+        assert_eq!(romanize("한국", Style::Dero), "hangug");
+        assert_eq!(romanize("한앙", Style::Dero), "han-ang");
+        assert_eq!(romanize("닭", Style::Dero), "dalg");
+    }
+
+    #[test]
+    fn dero_style_converts_back() {
+        let words = [
+            "한앙", "하낭", "한국", "항욱", "닭이", "닭", "달기", "아이", "아에", "애", "우이", "의",
+            "에오", "에우", "오에", "외", "으이", "있소", "있어", "부엌까지", "밖까지", "갔다",
+            "맛있는", "낮잠", "짧은", "앉아", "값이", "안녕하세요",
+        ];
+        for word in words.iter() {
+            assert_eq!(deromanize_escaped(&romanize(word, Style::Dero)), *word);
+        }
+    }
+
+    #[test]
+    fn spoken_styles_follow_the_sound_changes() {
+        assert_eq!(romanize("한국", Style::Revised), "hanguk");
+        assert_eq!(romanize("한국", Style::McCuneReischauer), "han'guk");
+        assert_eq!(romanize("신라", Style::Revised), "silla");
+        assert_eq!(romanize("독립", Style::Revised), "dongnip");
+        assert_eq!(romanize("좋아", Style::Revised), "joa");
+    }
+
+    #[test]
+    fn leaves_everything_else() {
+        assert_eq!(romanize("a 한, b", Style::Revised), "a han, b");
+    }
+
+    #[test]
+    fn gives_the_keys_of_each_jamo() {
+        assert_eq!(dero_keys('닭'), Some(("d", "a", Some("lg".to_string()))));
+        assert_eq!(dero_keys('아'), Some(("", "a", None)));
+        assert_eq!(dero_keys('a'), None);
+    }
+}
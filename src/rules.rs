@@ -0,0 +1,318 @@
+//! The user's own rules, applied around dero's conversion.
+//!
+//! The rules file is read from `$XDG_CONFIG_HOME/derowin/rules`, or the
+//! path set with `rules.file`, and is read again whenever it changes. It
+//! has a section for each kind of rule, with `#` comments:
+//!
+//! ```text
+//! [abbreviations]
+//! # Expanded before conversion
+//! kr = hangug
+//!
+//! [overrides]
+//! # Converted words to replace after conversion
+//! 서울 = 서울特別市
+//!
+//! [protected]
+//! # Words that are never converted
+//! GitHub
+//!
+//! [tests]
+//! # What text should convert to, for `derowin rules test`
+//! kr-eo = 한국어
+//! ```
+//!
+//! Rules match whole words, which are the text between spaces without the
+//! punctuation around it. Abbreviations and overrides also match each
+//! part of a hyphenated word, so `kr-eo` above is read as `hangug-eo`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use config::{self, Config};
+use error::DerowinError;
+
+/// How often frontends check the rules file for changes.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    pub abbreviations: BTreeMap<String, String>,
+    pub overrides: BTreeMap<String, String>,
+    pub protected: BTreeSet<String>,
+    /// Text and what it should convert to.
+    pub tests: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Abbreviations,
+    Overrides,
+    Protected,
+    Tests,
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Rules, DerowinError> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| DerowinError::File(path.display().to_string(), e))?;
+        Rules::parse(&contents, &path.display().to_string())
+    }
+
+    pub fn parse(contents: &str, source: &str) -> Result<Rules, DerowinError> {
+        let mut rules = Rules::default();
+        let mut section = None;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let origin = format!("{}:{}", source, i + 1);
+            let error = |reason: &str| DerowinError::Config(format!("{}: {}", origin, reason));
+            if line.starts_with('[') && line.ends_with(']') {
+                section = Some(match &line[1..line.len() - 1] {
+                    "abbreviations" => Section::Abbreviations,
+                    "overrides" => Section::Overrides,
+                    "protected" => Section::Protected,
+                    "tests" => Section::Tests,
+                    _ => return Err(error("unknown section")),
+                });
+                continue;
+            }
+            let section = section.ok_or_else(|| error("rule before any section"))?;
+            if section == Section::Protected {
+                rules.protected.insert(line.to_string());
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim().to_string();
+            let value = match parts.next() {
+                Some(value) if !key.is_empty() => value.trim().to_string(),
+                _ => return Err(error("expected 'text = replacement'")),
+            };
+            match section {
+                Section::Abbreviations => { rules.abbreviations.insert(key, value); }
+                Section::Overrides => { rules.overrides.insert(key, value); }
+                Section::Tests => rules.tests.push((key, value)),
+                Section::Protected => unreachable!(),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Converts text with `convert`, expanding abbreviations before and
+    /// overriding words after, and leaving protected words alone.
+    pub fn apply<F: Fn(&str) -> String>(&self, text: &str, convert: F) -> String {
+        let mut converted = String::with_capacity(text.len() * 2);
+        // What has been read since the last protected word, which is
+        // converted in one go.
+        let mut pending = String::with_capacity(text.len());
+        for (piece, core) in pieces(text) {
+            if self.protected.contains(&piece[core.0..core.1]) {
+                converted.push_str(&self.override_words(&convert(&pending)));
+                pending.clear();
+                converted.push_str(piece);
+            } else {
+                pending.push_str(&replace_core(piece, core, &self.abbreviations));
+            }
+        }
+        if !pending.is_empty() || converted.is_empty() {
+            converted.push_str(&self.override_words(&convert(&pending)));
+        }
+        converted
+    }
+
+    fn override_words(&self, text: &str) -> String {
+        if self.overrides.is_empty() {
+            return text.to_string();
+        }
+        pieces(text).into_iter()
+            .map(|(piece, core)| replace_core(piece, core, &self.overrides))
+            .collect()
+    }
+}
+
+/// Splits text into words and the spaces between them, with where each
+/// word is without its punctuation.
+fn pieces(text: &str) -> Vec<(&str, (usize, usize))> {
+    let outer = |ch: char| ch.is_ascii_punctuation() && ch != '-' && ch != '\'';
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while let Some(first) = rest.chars().next() {
+        let length = rest.find(|ch: char| ch.is_whitespace() != first.is_whitespace())
+            .unwrap_or(rest.len());
+        let piece = &rest[..length];
+        let core_start = piece.len() - piece.trim_start_matches(&outer).len();
+        let core_end = piece.trim_end_matches(&outer).len().max(core_start);
+        pieces.push((piece, (core_start, core_end)));
+        rest = &rest[length..];
+    }
+    pieces
+}
+
+/// The piece with its word replaced, if it is in the map, or otherwise
+/// with whichever of its hyphenated parts are.
+fn replace_core(piece: &str, core: (usize, usize), map: &BTreeMap<String, String>) -> String {
+    let word = &piece[core.0..core.1];
+    let replacement = match map.get(word) {
+        _ if word.is_empty() => return piece.to_string(),
+        Some(replacement) => replacement.clone(),
+        None if word.contains('-') => {
+            let parts: Vec<&str> = word.split('-')
+                .map(|part| map.get(part).map_or(part, |replacement| replacement.as_str()))
+                .collect();
+            parts.join("-")
+        }
+        None => return piece.to_string(),
+    };
+    format!("{}{}{}", &piece[..core.0], replacement, &piece[core.1..])
+}
+
+/// The rules file, and when it was last read.
+#[derive(Debug, Clone)]
+pub struct RuleFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    read: bool,
+}
+
+impl RuleFile {
+    pub fn new(path: PathBuf) -> RuleFile {
+        RuleFile {
+            path: path,
+            modified: None,
+            read: false,
+        }
+    }
+
+    /// The file from `rules.file`, or the default one.
+    pub fn from_config(config: &Config) -> Result<Option<RuleFile>, DerowinError> {
+        let path = match config.parse_with("rules.file", |path| Some(PathBuf::from(path)))? {
+            Some(path) => Some(path),
+            None => config::config_dir().map(|dir| dir.join("rules")),
+        };
+        Ok(path.map(RuleFile::new))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the rules if the file changed since they were last read, or
+    /// if they never were. There are none while the file is missing.
+    pub fn reload(&mut self) -> Option<Result<Option<Rules>, DerowinError>> {
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if self.read && modified == self.modified {
+            return None;
+        }
+        self.read = true;
+        self.modified = modified;
+        match modified {
+            Some(_) => Some(Rules::load(&self.path).map(Some)),
+            None => Some(Ok(None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::{RuleFile, Rules};
+
+    const RULES: &'static str = "\
+# A comment
+[abbreviations]
+kr = hangug
+
+[overrides]
+서울 = 서울特別市
+
+[protected]
+GitHub
+
+[tests]
+kr-eo = 한국어
+";
+
+    /// Stands in for dero, so that the tests show what the rules did.
+    fn shout(text: &str) -> String {
+        text.to_uppercase()
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let rules = Rules::parse(RULES, "rules").unwrap();
+        assert_eq!(rules.abbreviations.get("kr").map(|s| s.as_str()), Some("hangug"));
+        assert_eq!(rules.overrides.get("서울").map(|s| s.as_str()), Some("서울特別市"));
+        assert!(rules.protected.contains("GitHub"));
+        assert_eq!(rules.tests, vec![(String::from("kr-eo"), String::from("한국어"))]);
+    }
+
+    #[test]
+    fn reports_where_a_rule_is_wrong() {
+        let err = Rules::parse("[abbreviations]\nkr\n", "rules").unwrap_err();
+        assert!(err.to_string().contains("rules:2"), "{}", err);
+        assert!(Rules::parse("[nonsense]\n", "rules").is_err());
+        assert!(Rules::parse("kr = hangug\n", "rules").is_err());
+    }
+
+    #[test]
+    fn expands_abbreviations_before_converting() {
+        let rules = Rules::parse(RULES, "rules").unwrap();
+        assert_eq!(rules.apply("kr saram", shout), "HANGUG SARAM");
+        // Punctuation around a word doesn't stop it matching.
+        assert_eq!(rules.apply("(kr),", shout), "(HANGUG),");
+        // Only whole words do.
+        assert_eq!(rules.apply("krx", shout), "KRX");
+    }
+
+    #[test]
+    fn expands_hyphenated_parts() {
+        let rules = Rules::parse(RULES, "rules").unwrap();
+        assert_eq!(rules.apply("kr-eo", shout), "HANGUG-EO");
+        assert_eq!(rules.apply("eo-kr-eo!", shout), "EO-HANGUG-EO!");
+    }
+
+    #[test]
+    fn leaves_protected_words_alone() {
+        let rules = Rules::parse(RULES, "rules").unwrap();
+        assert_eq!(rules.apply("on GitHub now", shout), "ON GitHub NOW");
+        assert_eq!(rules.apply("GitHub", shout), "GitHub");
+    }
+
+    #[test]
+    fn overrides_converted_words() {
+        let rules = Rules::parse(RULES, "rules").unwrap();
+        let convert = |text: &str| text.replace("seoul", "서울");
+        assert_eq!(rules.apply("seoul.", convert), "서울特別市.");
+    }
+
+    #[test]
+    fn empty_rules_just_convert() {
+        assert_eq!(Rules::default().apply("", shout), "");
+        assert_eq!(Rules::default().apply(" a  b ", shout), " A  B ");
+    }
+
+    #[test]
+    fn reloads_when_the_file_changes() {
+        let path = env::temp_dir().join(format!("derowin-rules-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut rule_file = RuleFile::new(path.clone());
+        assert_eq!(rule_file.reload().map(|r| r.unwrap()), Some(None));
+        assert!(rule_file.reload().is_none());
+        fs::write(&path, RULES).unwrap();
+        let rules = rule_file.reload().unwrap().unwrap().unwrap();
+        assert!(rules.protected.contains("GitHub"));
+        assert!(rule_file.reload().is_none());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(rule_file.reload().map(|r| r.unwrap()), Some(None));
+    }
+}
@@ -0,0 +1,285 @@
+//! Journaling of the editing session, so that a crash doesn't lose what
+//! was typed.
+//!
+//! The session is written to `$XDG_STATE_HOME/derowin/session` (falling
+//! back to `~/.local/state`) as a small line-based text file.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use editor::{Editor, Snapshot};
+use DeroMode;
+
+/// How often (in seconds) unsaved changes are written to the journal.
+const AUTOSAVE_INTERVAL: u64 = 5;
+const HEADER: &'static str = "derowin-session 1";
+
+/// A session read back from the journal.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub mode: DeroMode,
+    pub editor: Editor,
+}
+
+/// Writes the session to disk at intervals and on commits.
+#[derive(Debug)]
+pub struct Journal {
+    path: Option<PathBuf>,
+    last_save: Instant,
+    last_contents: String,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal {
+            path: session_path(),
+            last_save: Instant::now(),
+            last_contents: String::new(),
+        }
+    }
+
+    /// Reads the previous session, if there is one with anything in it.
+    pub fn load(&self) -> Option<Session> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return None,
+        };
+        let mut contents = String::new();
+        match File::open(path).and_then(|mut file| file.read_to_string(&mut contents)) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!("Could not read session file {}: {}", path.display(), e);
+                return None;
+            }
+        }
+        match parse(&contents) {
+            Some(ref session) if session.editor.is_empty() => None,
+            Some(session) => {
+                info!("Found a previous session in {}", path.display());
+                Some(session)
+            }
+            None => {
+                warn!("Ignoring malformed session file {}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Saves the session if it has changed and the autosave interval has
+    /// passed.
+    pub fn tick(&mut self, mode: DeroMode, editor: &Editor) {
+        if self.last_save.elapsed() >= Duration::from_secs(AUTOSAVE_INTERVAL) {
+            self.save(mode, editor);
+        }
+    }
+
+    /// Saves the session now, if it has changed since the last save.
+    pub fn save(&mut self, mode: DeroMode, editor: &Editor) {
+        self.last_save = Instant::now();
+        let contents = serialize(mode, editor);
+        if contents == self.last_contents {
+            return;
+        }
+        if let Some(ref path) = self.path {
+            if let Err(e) = write_atomically(path, &contents) {
+                error!("Could not save session to {}: {}", path.display(), e);
+                return;
+            }
+            debug!("Saved session to {}", path.display());
+        }
+        self.last_contents = contents;
+    }
+
+    /// Removes the journal, for when the session ends with nothing to keep.
+    pub fn discard(&mut self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Could not remove session file {}: {}", path.display(), e);
+                }
+            }
+        }
+        self.last_contents.clear();
+    }
+}
+
+/// The `derowin` directory under `$XDG_STATE_HOME` or `~/.local/state`.
+pub fn state_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_STATE_HOME") {
+        Some(ref dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("derowin")),
+        _ => env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".local").join("state").join("derowin")),
+    }
+}
+
+fn session_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("session"))
+}
+
+/// Writes a file by way of a temporary one beside it, so that a crash
+/// leaves either the old contents or the new. It is only readable by us,
+/// as it may hold whatever was typed.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn serialize(mode: DeroMode, editor: &Editor) -> String {
+    let mut out = String::new();
+    out.push_str(HEADER);
+    out.push('\n');
+    out.push_str(&format!("mode {}\n", mode.name()));
+    out.push_str(&format!("caret {}\n", editor.caret()));
+    out.push_str(&format!("text {}\n", escape(editor.text())));
+    let (undo, redo) = editor.history();
+    for snapshot in undo {
+        out.push_str(&format!("undo {} {}\n", snapshot.caret, escape(&snapshot.text)));
+    }
+    for snapshot in redo {
+        out.push_str(&format!("redo {} {}\n", snapshot.caret, escape(&snapshot.text)));
+    }
+    out
+}
+
+fn parse(contents: &str) -> Option<Session> {
+    let mut lines = contents.lines();
+    if lines.next() != Some(HEADER) {
+        return None;
+    }
+    let mut mode = DeroMode::Default;
+    let mut caret = 0;
+    let mut text = String::new();
+    let mut undo = Vec::new();
+    let mut redo = Vec::new();
+    for line in lines {
+        let mut parts = line.splitn(2, ' ');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+        match key {
+            "mode" => mode = DeroMode::from_name(value)?,
+            "caret" => caret = value.parse().ok()?,
+            "text" => text = unescape(value),
+            "undo" | "redo" => {
+                let mut parts = value.splitn(2, ' ');
+                let caret = parts.next()?.parse().ok()?;
+                let text = unescape(parts.next().unwrap_or(""));
+                let snapshot = Snapshot { text: text, caret: caret };
+                if key == "undo" {
+                    undo.push(snapshot);
+                } else {
+                    redo.push(snapshot);
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(Session {
+        mode: mode,
+        editor: Editor::from_parts(text, caret, undo, redo),
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use editor::{Editor, Snapshot};
+    use DeroMode;
+
+    use super::{escape, parse, serialize, unescape};
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let undo = vec![
+            Snapshot { text: String::new(), caret: 0 },
+            Snapshot { text: String::from("han"), caret: 3 },
+        ];
+        let redo = vec![Snapshot { text: String::from("hangug\neo"), caret: 2 }];
+        let editor = Editor::from_parts(String::from("hangug\\\n"), 6, undo, redo);
+        let session = parse(&serialize(DeroMode::Romanize, &editor)).unwrap();
+        assert_eq!(session.mode, DeroMode::Romanize);
+        assert_eq!(session.editor.text(), editor.text());
+        assert_eq!(session.editor.caret(), 6);
+        assert_eq!(session.editor.history(), editor.history());
+    }
+
+    #[test]
+    fn ignores_other_files() {
+        assert!(parse("").is_none());
+        assert!(parse("derowin-session 2\ntext a\n").is_none());
+        assert!(parse("derowin-session 1\nsomething else\n").is_none());
+        assert!(parse("derowin-session 1\ncaret many\n").is_none());
+    }
+
+    #[test]
+    fn escapes_line_breaks_and_backslashes() {
+        let text = "a\\n\nb\r\n\\";
+        assert!(!escape(text).contains('\n'));
+        assert_eq!(unescape(&escape(text)), text);
+        // A backslash left at the end is kept.
+        assert_eq!(unescape("a\\"), "a\\");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_we_can_read_the_session() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::{env, fs, process};
+
+        use super::write_atomically;
+
+        let dir = env::temp_dir().join(format!("derowin-session-test-{}", process::id()));
+        let path = dir.join("session");
+        write_atomically(&path, "derowin-session 1\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,123 @@
+//! The status bar at the bottom of the window.
+
+use rsdl2::Renderer;
+use rsdl2_font::rusttype::{Font, Scale};
+
+use error::DerowinError;
+use normalize;
+use output::Sink;
+use render;
+use theme::Theme;
+use DeroMode;
+
+const STATUS_POINT_SIZE: f32 = 13.0;
+const SEPARATOR: &'static str = "  ·  ";
+/// How much of the last committed text is shown.
+const LAST_COMMIT_CHARS: usize = 12;
+
+#[derive(Debug, Clone)]
+pub struct StatusBar {
+    last_commit: Option<String>,
+    output_ok: bool,
+    /// What the last background job said, until the next commit.
+    message: Option<String>,
+}
+
+impl StatusBar {
+    pub fn new() -> StatusBar {
+        StatusBar {
+            last_commit: None,
+            output_ok: true,
+            message: None,
+        }
+    }
+
+    pub fn committed(&mut self, text: &str) {
+        // Only what is left out shows the ellipsis, not a trailing newline.
+        let text = text.trim_end();
+        let mut shown = text.lines().next().unwrap_or("").chars()
+            .take(LAST_COMMIT_CHARS)
+            .collect::<String>();
+        if shown.len() < text.len() {
+            shown.push('…');
+        }
+        self.last_commit = Some(shown);
+        self.message = None;
+    }
+
+    pub fn show(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    /// Notes whether the last use of the clipboard, or of typing, worked.
+    pub fn output_used<T>(&mut self, result: &Result<T, DerowinError>) {
+        self.output_ok = match *result {
+            Err(DerowinError::Clipboard(..)) | Err(DerowinError::Typing(..)) => false,
+            _ => true,
+        };
+    }
+
+    pub fn height(font: &Font) -> i32 {
+        let v_metrics = font.v_metrics(Scale::uniform(STATUS_POINT_SIZE));
+        (v_metrics.ascent - v_metrics.descent).ceil() as i32 + 6
+    }
+
+    /// Draws the bar along the bottom of a window of the given size.
+    /// `converted` is the whole buffer after conversion.
+    pub fn draw(&self, renderer: &Renderer, font: &Font, theme: &Theme, mode: DeroMode,
+        output: &Sink, converted: &str, window_size: (i32, i32))
+        -> Result<(), DerowinError>
+    {
+        let (width, height) = window_size;
+        let top = height - StatusBar::height(font);
+        render::fill_rect(renderer, theme.accent(mode), 0, top, width, 1)?;
+
+        let chars = converted.chars().filter(|&ch| ch != '\n').count();
+        let syllables = converted.chars().filter(|&ch| normalize::is_syllable(ch)).count();
+        let output_status = if self.output_ok {
+            output.describe()
+        } else {
+            format!("{} failed", output.describe())
+        };
+        let mut details = format!("{}{} chars{}{} syllables{}{}",
+            SEPARATOR, chars, SEPARATOR, syllables, SEPARATOR, output_status);
+        if let Some(ref last) = self.last_commit {
+            details.push_str(&format!("{}last: {}", SEPARATOR, last));
+        }
+        if let Some(ref message) = self.message {
+            details.push_str(&format!("{}{}", SEPARATOR, message));
+        }
+
+        let (x, y) = (theme.padding.0, top + 3);
+        let mode_width = render::draw_text_at(renderer, font, mode.name(), theme.accent(mode),
+            STATUS_POINT_SIZE, x, y)?;
+        render::draw_text_at(renderer, font, &details, theme.foreground,
+            STATUS_POINT_SIZE, x + mode_width, y)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatusBar, LAST_COMMIT_CHARS};
+
+    fn last_commit(text: &str) -> String {
+        let mut status = StatusBar::new();
+        status.committed(text);
+        status.last_commit.unwrap()
+    }
+
+    #[test]
+    fn shows_whole_commits_without_an_ellipsis() {
+        assert_eq!(last_commit("한국"), "한국");
+        assert_eq!(last_commit("한국\n"), "한국");
+        assert_eq!(last_commit("한국  \r\n"), "한국");
+    }
+
+    #[test]
+    fn marks_what_is_left_out() {
+        assert_eq!(last_commit("한국\n어"), "한국…");
+        let long = "가".repeat(LAST_COMMIT_CHARS + 1);
+        assert_eq!(last_commit(&long), "가".repeat(LAST_COMMIT_CHARS) + "…");
+    }
+}
@@ -0,0 +1,247 @@
+//! Colors and text layout settings.
+//!
+//! A theme starts from one of the built-in themes (chosen with the `theme`
+//! config key or `--theme`), and any `theme.*` key overrides a part of it.
+//! `auto` picks `light` or `dark` following the desktop, when it says.
+
+use std::process::Command;
+
+use config::Config;
+use error::DerowinError;
+use DeroMode;
+
+pub type Color = (u8, u8, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaretShape {
+    Bar,
+    Block,
+    Underline,
+}
+
+impl CaretShape {
+    pub fn from_name(name: &str) -> Option<CaretShape> {
+        match name {
+            "bar" => Some(CaretShape::Bar),
+            "block" => Some(CaretShape::Block),
+            "underline" => Some(CaretShape::Underline),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub background: Color,
+    pub foreground: Color,
+    pub selection: Color,
+    pub caret: Color,
+    /// Romanization that was left unconverted.
+    pub unconverted: Color,
+    /// The notes over annotated words.
+    pub annotation: Color,
+    /// The background of text that is kept literal.
+    pub literal: Color,
+    pub error: Color,
+    pub default_accent: Color,
+    pub input_accent: Color,
+    pub lookup_accent: Color,
+    pub romanize_accent: Color,
+    /// Space between the window edges and the text, in pixels.
+    pub padding: (i32, i32),
+    pub caret_shape: CaretShape,
+    /// The caret while typing literal text.
+    pub literal_caret_shape: CaretShape,
+    /// Milliseconds between caret blinks, or 0 for a steady caret.
+    pub blink_rate: u64,
+}
+
+impl Theme {
+    pub fn light() -> Theme {
+        Theme {
+            background: (255, 255, 255),
+            foreground: (0, 0, 0),
+            selection: (179, 215, 255),
+            caret: (0, 0, 0),
+            unconverted: (200, 0, 0),
+            annotation: (110, 110, 110),
+            literal: (226, 240, 226),
+            error: (200, 0, 0),
+            default_accent: (160, 160, 160),
+            input_accent: (40, 120, 220),
+            lookup_accent: (220, 140, 20),
+            romanize_accent: (40, 160, 90),
+            padding: (10, 10),
+            caret_shape: CaretShape::Underline,
+            literal_caret_shape: CaretShape::Bar,
+            blink_rate: 500,
+        }
+    }
+
+    pub fn dark() -> Theme {
+        Theme {
+            background: (30, 30, 30),
+            foreground: (230, 230, 230),
+            selection: (38, 79, 120),
+            caret: (230, 230, 230),
+            unconverted: (240, 110, 110),
+            annotation: (160, 160, 160),
+            literal: (40, 62, 44),
+            error: (240, 90, 90),
+            default_accent: (110, 110, 110),
+            input_accent: (80, 160, 255),
+            lookup_accent: (255, 180, 60),
+            romanize_accent: (90, 200, 130),
+            .. Theme::light()
+        }
+    }
+
+    pub fn solarized_light() -> Theme {
+        Theme {
+            background: (253, 246, 227),
+            foreground: (101, 123, 131),
+            selection: (238, 232, 213),
+            caret: (88, 110, 117),
+            unconverted: (220, 50, 47),
+            annotation: (147, 161, 161),
+            literal: (230, 235, 200),
+            error: (220, 50, 47),
+            default_accent: (147, 161, 161),
+            input_accent: (38, 139, 210),
+            lookup_accent: (181, 137, 0),
+            romanize_accent: (133, 153, 0),
+            .. Theme::light()
+        }
+    }
+
+    pub fn solarized_dark() -> Theme {
+        Theme {
+            background: (0, 43, 54),
+            foreground: (131, 148, 150),
+            selection: (7, 54, 66),
+            caret: (147, 161, 161),
+            annotation: (88, 110, 117),
+            literal: (20, 64, 48),
+            .. Theme::solarized_light()
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "light" => Some(Theme::light()),
+            "dark" => Some(Theme::dark()),
+            "solarized-light" => Some(Theme::solarized_light()),
+            "solarized-dark" => Some(Theme::solarized_dark()),
+            "auto" => Some(if desktop_prefers_dark() { Theme::dark() } else { Theme::light() }),
+            _ => None,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Theme, DerowinError> {
+        let name = config.get("theme").unwrap_or("auto");
+        let mut theme = config.parse_with("theme", Theme::builtin)?
+            .unwrap_or_else(|| Theme::builtin("auto").unwrap());
+        info!("Using the {} theme", name);
+        {
+            let color = |key: &str, target: &mut Color| -> Result<(), DerowinError> {
+                if let Some(value) = config.parse_with(key, parse_color)? {
+                    *target = value;
+                }
+                Ok(())
+            };
+            color("theme.background", &mut theme.background)?;
+            color("theme.foreground", &mut theme.foreground)?;
+            color("theme.selection", &mut theme.selection)?;
+            color("theme.caret", &mut theme.caret)?;
+            color("theme.unconverted", &mut theme.unconverted)?;
+            color("theme.annotation", &mut theme.annotation)?;
+            color("theme.literal", &mut theme.literal)?;
+            color("theme.error", &mut theme.error)?;
+            color("theme.accent.default", &mut theme.default_accent)?;
+            color("theme.accent.input", &mut theme.input_accent)?;
+            color("theme.accent.lookup", &mut theme.lookup_accent)?;
+            color("theme.accent.romanize", &mut theme.romanize_accent)?;
+        }
+        if let Some(padding) = config.parse_with("theme.padding", parse_padding)? {
+            theme.padding = padding;
+        }
+        if let Some(shape) = config.parse_with("theme.caret-shape", CaretShape::from_name)? {
+            theme.caret_shape = shape;
+        }
+        let literal_shape = config.parse_with("theme.literal-caret-shape", CaretShape::from_name)?;
+        if let Some(shape) = literal_shape {
+            theme.literal_caret_shape = shape;
+        }
+        if let Some(rate) = config.parse_value("theme.blink-rate")? {
+            theme.blink_rate = rate;
+        }
+        Ok(theme)
+    }
+
+    pub fn accent(&self, mode: DeroMode) -> Color {
+        match mode {
+            DeroMode::Default => self.default_accent,
+            DeroMode::Input => self.input_accent,
+            DeroMode::Lookup => self.lookup_accent,
+            DeroMode::Romanize => self.romanize_accent,
+        }
+    }
+}
+
+/// Parses `#rrggbb` or `r, g, b`.
+pub fn parse_color(value: &str) -> Option<Color> {
+    if value.starts_with('#') {
+        let hex = &value[1..];
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some((channel(0)?, channel(2)?, channel(4)?));
+    }
+    let parts = value.split(',')
+        .map(|part| part.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if parts.len() == 3 {
+        Some((parts[0], parts[1], parts[2]))
+    } else {
+        None
+    }
+}
+
+/// Parses `all` or `horizontal, vertical` padding.
+fn parse_padding(value: &str) -> Option<(i32, i32)> {
+    let parts = value.split(',')
+        .map(|part| part.trim().parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts.len() {
+        1 => Some((parts[0], parts[0])),
+        2 => Some((parts[0], parts[1])),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn desktop_prefers_dark() -> bool {
+    // Only set when dark mode is on.
+    match Command::new("defaults").args(&["read", "-g", "AppleInterfaceStyle"]).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim() == "Dark",
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn desktop_prefers_dark() -> bool {
+    if let Ok(gtk_theme) = ::std::env::var("GTK_THEME") {
+        return gtk_theme.ends_with(":dark");
+    }
+    let output = Command::new("gsettings")
+        .args(&["get", "org.gnome.desktop.interface", "color-scheme"])
+        .output();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains("prefer-dark"),
+        Err(_) => {
+            debug!("Could not ask gsettings for the color scheme");
+            false
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! What is done to converted text before it is committed.
+//!
+//! Each mode has its own steps, set with `transform.<mode>.<step>`, or for
+//! every mode at once with `transform.<step>`. They are done in this order:
+//!
+//! - `annotate = html|markdown`: put what each word was typed as over it,
+//!   as `<ruby>` tags or as `{한국|hangug}` (`--annotate` sets it for
+//!   every mode)
+//! - `normalize = nfc|nfd|compatibility`: the form to write Hangul in
+//!   (left as dero gives it if unset)
+//! - `collapse-whitespace = on`: turn runs of spaces and tabs into one
+//!   space, and drop them at the ends of lines
+//! - `template`: what to commit instead, where `{text}` is the converted
+//!   text, `{source}` what was typed and `{romanized}` the text in the
+//!   romanization style, like `<ruby>{text}<rt>{romanized}</rt></ruby>`
+//!   or `{text} ({source})`
+//! - `command`: a shell command that is given the text on its standard
+//!   input, and whose output is committed instead
+//! - `command-timeout`: how many seconds the command may take before it is
+//!   stopped, along with anything it started, and the commit fails (5 by
+//!   default). The window waits for the command, so keep it short
+//! - `trim-newline = on`: drop newlines at the end
+//! - `encoding = cp949`: warn about characters that CP949 can't represent
+//!   (the text is committed anyway)
+
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use encoding_rs::EUC_KR;
+#[cfg(unix)]
+use libc;
+
+use annotate::{self, Annotation};
+use clipboard::check_status;
+use compose::Converter;
+use config::{self, Config};
+use error::DerowinError;
+use logging::redact;
+use mapping::Mapping;
+use normalize::{self, Form};
+use romanize::{self, Style};
+use DeroMode;
+
+const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 5;
+
+/// How often a running command is checked on.
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Unicode,
+    Cp949,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Unicode => "unicode",
+            Encoding::Cp949 => "cp949",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "unicode" | "utf-8" | "utf8" => Some(Encoding::Unicode),
+            "cp949" | "euc-kr" | "uhc" => Some(Encoding::Cp949),
+            _ => None,
+        }
+    }
+
+    fn represents(&self, ch: char) -> bool {
+        match *self {
+            Encoding::Unicode => true,
+            Encoding::Cp949 => ch.is_ascii() || !EUC_KR.encode(&ch.to_string()).2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transform {
+    pub annotation: Option<Annotation>,
+    pub form: Option<Form>,
+    pub trim_newline: bool,
+    pub collapse_whitespace: bool,
+    pub template: Option<String>,
+    pub command: Option<String>,
+    pub command_timeout: Duration,
+    pub encoding: Encoding,
+}
+
+impl Transform {
+    fn from_config(config: &Config, mode: DeroMode) -> Result<Transform, DerowinError> {
+        let flag = |step: &str| step_value(config, mode, step, config::parse_bool)
+            .map(|on| on.unwrap_or(false));
+        Ok(Transform {
+            annotation: step_value(config, mode, "annotate", Annotation::from_name)?,
+            form: step_value(config, mode, "normalize", Form::from_name)?,
+            trim_newline: flag("trim-newline")?,
+            collapse_whitespace: flag("collapse-whitespace")?,
+            template: step_value(config, mode, "template", |v| Some(v.to_string()))?,
+            command: step_value(config, mode, "command", |v| Some(v.to_string()))?,
+            command_timeout: step_value(config, mode, "command-timeout", |v| v.parse().ok())?
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(DEFAULT_COMMAND_TIMEOUT_SECS)),
+            encoding: step_value(config, mode, "encoding", Encoding::from_name)?
+                .unwrap_or(Encoding::Unicode),
+        })
+    }
+
+    /// Transforms the converted text, which was typed as `source` in the
+    /// mode, also returning the characters the encoding can't represent,
+    /// once each.
+    pub fn apply(&self, converter: &Converter, text: &str, source: &str, mode: DeroMode,
+        style: Style)
+        -> Result<(String, Vec<char>), DerowinError>
+    {
+        // Romanized from the converted text as it is, before the steps
+        // below add notes or put it in another form.
+        let romanized = match self.template {
+            Some(_) => romanize::romanize(&normalize::compose(text), style),
+            None => String::new(),
+        };
+        let mut text = match self.annotation {
+            Some(annotation) => {
+                let mapping = Mapping::new(source, |text| converter.convert_in(mode, style, text));
+                let segments = annotate::segments(&mapping, source, mode == DeroMode::Romanize);
+                annotation.write(&segments)
+            }
+            None => text.to_string(),
+        };
+        if let Some(form) = self.form {
+            text = normalize::normalize(&text, form);
+        }
+        if self.collapse_whitespace {
+            text = collapse_whitespace(&text);
+        }
+        if let Some(ref template) = self.template {
+            let values = [("text", text.as_str()), ("source", source), ("romanized", &romanized)];
+            text = expand(template, &values);
+        }
+        if let Some(ref command) = self.command {
+            text = run_hook(command, &text, self.command_timeout)?;
+        }
+        if self.trim_newline {
+            let kept = text.trim_end_matches(|ch| ch == '\n' || ch == '\r').len();
+            text.truncate(kept);
+        }
+        let mut unrepresentable = Vec::new();
+        for ch in text.chars() {
+            if !self.encoding.represents(ch) && !unrepresentable.contains(&ch) {
+                unrepresentable.push(ch);
+            }
+        }
+        Ok((text, unrepresentable))
+    }
+
+    /// A warning about the characters `apply` found, if there were any.
+    pub fn warning(&self, unrepresentable: &[char]) -> Option<String> {
+        if unrepresentable.is_empty() {
+            return None;
+        }
+        let chars = unrepresentable.iter().map(|ch| ch.to_string()).collect::<Vec<_>>();
+        Some(format!("Not in {}: {}", self.encoding.name().to_uppercase(), chars.join(" ")))
+    }
+}
+
+/// The transform of each mode.
+#[derive(Debug, Clone)]
+pub struct Transforms {
+    default: Transform,
+    input: Transform,
+    lookup: Transform,
+    romanize: Transform,
+}
+
+impl Transforms {
+    pub fn from_config(config: &Config) -> Result<Transforms, DerowinError> {
+        Ok(Transforms {
+            default: Transform::from_config(config, DeroMode::Default)?,
+            input: Transform::from_config(config, DeroMode::Input)?,
+            lookup: Transform::from_config(config, DeroMode::Lookup)?,
+            romanize: Transform::from_config(config, DeroMode::Romanize)?,
+        })
+    }
+
+    pub fn get(&self, mode: DeroMode) -> &Transform {
+        match mode {
+            DeroMode::Default => &self.default,
+            DeroMode::Input => &self.input,
+            DeroMode::Lookup => &self.lookup,
+            DeroMode::Romanize => &self.romanize,
+        }
+    }
+}
+
+/// Reads a step's value for the mode, or failing that for every mode.
+fn step_value<T, F>(config: &Config, mode: DeroMode, step: &str, parse: F)
+    -> Result<Option<T>, DerowinError>
+    where F: Fn(&str) -> Option<T>
+{
+    match config.parse_with(&format!("transform.{}.{}", mode.name(), step), &parse)? {
+        Some(value) => Ok(Some(value)),
+        None => config.parse_with(&format!("transform.{}", step), &parse),
+    }
+}
+
+/// Fills in the `{name}`s in a template. Anything else in braces is kept.
+fn expand(template: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = String::with_capacity(template.len() * 2);
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        expanded.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}')
+            .and_then(|close| values.iter().find(|&&(name, _)| name == &rest[1..close]))
+            .map(|&(name, value)| (name.len() + 2, value));
+        match value {
+            Some((length, value)) => {
+                expanded.push_str(value);
+                rest = &rest[length..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Runs a command with `sh -c`, giving it the text and returning what it
+/// printed. This blocks until the command is done, for at most `timeout`,
+/// after which it is stopped along with anything it started.
+fn run_hook(command: &str, text: &str, timeout: Duration) -> Result<String, DerowinError> {
+    debug!("Running {} on {}", command, redact(text));
+    let hook_err = |e| DerowinError::Hook(command.to_string(), e);
+    let timed_out = || hook_err(io::Error::new(io::ErrorKind::TimedOut,
+        format!("took longer than {} seconds", timeout.as_secs())));
+    let mut shell = Command::new("sh");
+    shell.args(&["-c", command]).stdin(Stdio::piped()).stdout(Stdio::piped());
+    // In a process group of its own, so that what it starts can be stopped
+    // with it.
+    #[cfg(unix)]
+    shell.process_group(0);
+    let mut child = shell.spawn().map_err(&hook_err)?;
+    let (mut stdin, mut stdout) = match (child.stdin.take(), child.stdout.take()) {
+        (Some(stdin), Some(stdout)) => (stdin, stdout),
+        _ => unreachable!(),
+    };
+    // Written and read on threads of their own, so a command that prints
+    // before it has read everything can't leave both sides waiting. Dropping
+    // stdin once written closes it, so the command sees the end.
+    let input = text.to_string();
+    let writer = thread::spawn(move || match stdin.write_all(input.as_bytes()) {
+        // Commands don't have to read all of their input.
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    });
+    let (sender, printed) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(&hook_err)? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            stop_hook(&mut child);
+            return Err(timed_out());
+        }
+        thread::sleep(HOOK_POLL_INTERVAL);
+    };
+    check_status(status).map_err(&hook_err)?;
+    // Something the command started in the background can keep its output
+    // open after it has exited, so that is waited on until the deadline too,
+    // and stopped then so the thread reading it can finish.
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let output = match printed.recv_timeout(remaining) {
+        Ok(output) => output.map_err(&hook_err)?,
+        Err(_) => {
+            stop_hook(&mut child);
+            return Err(timed_out());
+        }
+    };
+    writer.join().unwrap_or(Ok(())).map_err(&hook_err)?;
+    Ok(String::from_utf8_lossy(&output).to_string())
+}
+
+/// Kills a hook and, where there are process groups, everything in its
+/// group.
+fn stop_hook(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Turns runs of spaces and tabs into one space, dropping them at the
+/// ends of lines.
+fn collapse_whitespace(text: &str) -> String {
+    let lines = text.split('\n').map(|line| {
+        let (line, ending) = if line.ends_with('\r') {
+            (&line[..line.len() - 1], "\r")
+        } else {
+            (line, "")
+        };
+        let words = line.split(|ch| ch == ' ' || ch == '\t')
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        format!("{}{}", words.join(" "), ending)
+    });
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use annotate::Annotation;
+    use compose::Converter;
+    use normalize::{self, Form};
+    use romanize::Style;
+    use DeroMode;
+
+    use super::{collapse_whitespace, expand, run_hook, Encoding, Transform};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn transform() -> Transform {
+        Transform {
+            annotation: None,
+            form: None,
+            trim_newline: false,
+            collapse_whitespace: false,
+            template: None,
+            command: None,
+            command_timeout: TIMEOUT,
+            encoding: Encoding::Unicode,
+        }
+    }
+
+    #[test]
+    fn romanizes_the_text_before_the_other_steps() {
+        let transform = Transform {
+            annotation: Some(Annotation::Markdown),
+            form: Some(Form::Nfd),
+            template: Some("{romanized}".to_string()),
+            ..transform()
+        };
+        let (text, _) = transform.apply(&Converter::new(), &normalize::decompose("한국"),
+            "hangug", DeroMode::Default, Style::Revised).unwrap();
+        assert_eq!(text, "hanguk");
+    }
+
+    #[test]
+    fn expands_known_names_only() {
+        let values = [("text", "한국"), ("source", "hangug")];
+        assert_eq!(expand("{text} ({source})", &values), "한국 (hangug)");
+        assert_eq!(expand("{other} {text", &values), "{other} {text");
+    }
+
+    #[test]
+    fn collapses_whitespace_per_line() {
+        assert_eq!(collapse_whitespace("  a \t b  \r\nc  d "), "a b\r\nc d");
+    }
+
+    #[test]
+    fn hook_gets_the_text_and_returns_its_output() {
+        assert_eq!(run_hook("tr a-z A-Z", "hangug\n", TIMEOUT).unwrap(), "HANGUG\n");
+    }
+
+    #[test]
+    fn hook_output_larger_than_a_pipe_does_not_deadlock() {
+        let text = "가나다라\n".repeat(100_000);
+        assert_eq!(run_hook("cat", &text, TIMEOUT).unwrap(), text);
+    }
+
+    #[test]
+    fn hook_need_not_read_its_input() {
+        let text = "x".repeat(1 << 20);
+        assert_eq!(run_hook("echo done", &text, TIMEOUT).unwrap(), "done\n");
+    }
+
+    #[test]
+    fn hook_that_fails_is_an_error() {
+        assert!(run_hook("cat; exit 3", "text", TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn hook_is_stopped_after_the_timeout() {
+        let start = Instant::now();
+        assert!(run_hook("sleep 10", "", Duration::from_millis(200)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn hook_is_stopped_with_what_it_started() {
+        use std::{env, fs, process};
+
+        let pid_file = env::temp_dir().join(format!("derowin-hook-test-{}", process::id()));
+        let command = format!("sleep 10 & echo $! > {}; wait", pid_file.display());
+        assert!(run_hook(&command, "", Duration::from_millis(200)).is_err());
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        fs::remove_file(&pid_file).unwrap();
+        // Gone, or waiting to be reaped.
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(stat.map_or(true, |stat| stat.contains(") Z ")));
+    }
+}
@@ -0,0 +1,217 @@
+//! A StatusNotifierItem tray icon, with a menu for the modes and quitting.
+
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use ksni::{self, MenuItem, TrayService};
+use ksni::menu::{RadioGroup, RadioItem, StandardItem};
+
+use control::Control;
+use DeroMode;
+
+struct DeroTray {
+    mode: DeroMode,
+    sender: Sender<Control>,
+}
+
+impl DeroTray {
+    fn send(&self, control: Control) {
+        // The main loop is gone if this fails, and will take the tray along.
+        let _ = self.sender.send(control);
+    }
+}
+
+impl ksni::Tray for DeroTray {
+    fn id(&self) -> String {
+        String::from("derowin")
+    }
+
+    fn title(&self) -> String {
+        self.mode.title()
+    }
+
+    fn icon_name(&self) -> String {
+        String::from("input-keyboard")
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.send(Control::ToggleWindow);
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        vec![
+            StandardItem {
+                label: String::from("Show/Hide"),
+                activate: Box::new(|tray: &mut DeroTray| tray.send(Control::ToggleWindow)),
+                .. Default::default()
+            }.into(),
+            MenuItem::Separator,
+            RadioGroup {
+                selected: DeroMode::ALL.iter().position(|&m| m == self.mode).unwrap_or(0),
+                select: Box::new(|tray: &mut DeroTray, i| {
+                    tray.send(Control::SetMode(DeroMode::ALL[i]));
+                }),
+                options: DeroMode::ALL.iter()
+                    .map(|mode| RadioItem {
+                        label: mode.label().to_string(),
+                        .. Default::default()
+                    })
+                    .collect(),
+            }.into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: String::from("Quit"),
+                activate: Box::new(|tray: &mut DeroTray| tray.send(Control::Quit)),
+                .. Default::default()
+            }.into(),
+        ]
+    }
+}
+
+pub struct Tray {
+    handle: ksni::Handle<DeroTray>,
+}
+
+impl Tray {
+    /// Shows the tray icon, sending its requests to the main loop.
+    pub fn spawn(sender: Sender<Control>, mode: DeroMode) -> Tray {
+        let service = TrayService::new(DeroTray { mode: mode, sender: sender });
+        let handle = service.handle();
+        thread::spawn(move || {
+            if let Err(e) = service.run() {
+                warn!("Could not show the tray icon: {}", e);
+            }
+        });
+        info!("Tray icon started");
+        Tray { handle: handle }
+    }
+
+    /// Keeps the mode shown in the menu in step with the window.
+    pub fn set_mode(&self, mode: DeroMode) {
+        self.handle.update(|tray| tray.mode = mode);
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        self.handle.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use dbus::arg::Variant;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::Connection;
+
+    use control::Control;
+    use DeroMode;
+
+    use super::Tray;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const ITEM_INTERFACE: &'static str = "org.kde.StatusNotifierItem";
+
+    /// A session bus of the test's own, stopped when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Option<Bus> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(&["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            let stdout = daemon.stdout.take()?;
+            BufReader::new(stdout).read_line(&mut address).ok()?;
+            Some(Bus { daemon: daemon, address: address.trim().to_string() })
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Stands in for the desktop's StatusNotifierWatcher, passing on the
+    /// names of the items that register.
+    fn watcher(address: &str) -> Receiver<String> {
+        let (sender, registered) = mpsc::channel();
+        let (ready_sender, ready) = mpsc::channel();
+        let address = address.to_string();
+        thread::spawn(move || {
+            let connection = Connection::new_address(&address).unwrap();
+            connection.request_name("org.kde.StatusNotifierWatcher", false, true, false).unwrap();
+            ready_sender.send(()).unwrap();
+            let channel = connection.channel();
+            while let Ok(Some(call)) = channel.blocking_pop_message(TIMEOUT) {
+                if call.member().map_or(false, |member| &*member == "RegisterStatusNotifierItem") {
+                    if let Ok(name) = call.read1::<String>() {
+                        let _ = sender.send(name);
+                    }
+                    let _ = channel.send(call.method_return());
+                }
+            }
+        });
+        ready.recv().unwrap();
+        registered
+    }
+
+    fn toggled(controls: &Receiver<Control>) -> bool {
+        match controls.recv_timeout(TIMEOUT) {
+            Ok(Control::ToggleWindow) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn tray_talks_to_the_desktop() {
+        let bus = match Bus::start() {
+            Some(bus) => bus,
+            None => {
+                eprintln!("No dbus-daemon to test the tray with");
+                return;
+            }
+        };
+        env::set_var("DBUS_SESSION_BUS_ADDRESS", &bus.address);
+        let registered = watcher(&bus.address);
+        let (sender, controls) = mpsc::channel();
+        let tray = Tray::spawn(sender, DeroMode::Default);
+        let name = registered.recv_timeout(TIMEOUT).unwrap();
+        assert!(name.starts_with("org.kde.StatusNotifierItem-"), "{}", name);
+
+        let connection = Connection::new_address(&bus.address).unwrap();
+        let item = connection.with_proxy(name.as_str(), "/StatusNotifierItem", TIMEOUT);
+        let menu = connection.with_proxy(name.as_str(), "/MenuBar", TIMEOUT);
+        let title = || item.get::<String>(ITEM_INTERFACE, "Title").unwrap();
+        assert_eq!(title(), DeroMode::Default.title());
+
+        // Clicking the icon, or Show/Hide in its menu, toggles the window.
+        let () = item.method_call(ITEM_INTERFACE, "Activate", (0i32, 0i32)).unwrap();
+        assert!(toggled(&controls));
+        let event = (1i32, "clicked", Variant(0i32), 0u32);
+        let () = menu.method_call("com.canonical.dbusmenu", "Event", event).unwrap();
+        assert!(toggled(&controls));
+
+        tray.set_mode(DeroMode::Input);
+        let start = Instant::now();
+        while title() != DeroMode::Input.title() {
+            assert!(start.elapsed() < TIMEOUT, "the title never changed");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Typing text into the focused window, as if it came from the keyboard.
+//!
+//! The backend is chosen with the `output.typer` config key:
+//!
+//! - `xtest`: the X11 XTest extension, remapping a spare keycode to each
+//!   character in turn (Linux, X11)
+//! - `uinput`: a virtual keyboard that types each character as a
+//!   Ctrl+Shift+U code point, which GTK and IBus understand (Linux, needs
+//!   write access to `/dev/uinput`)
+//! - `xdotool`: the `xdotool type` command (X11)
+//! - `ydotool`: the `ydotool type` command (Wayland). It can only type
+//!   what is on the keyboard layout, so it won't type Hangul without help.
+//! - `auto` (the default): `xtest` on X11 and `uinput` on Wayland
+
+#[cfg(target_os = "linux")]
+use std::env;
+use std::process::Command;
+
+use clipboard;
+use error::DerowinError;
+use logging::redact;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Typer {
+    #[cfg(target_os = "linux")]
+    XTest,
+    #[cfg(target_os = "linux")]
+    Uinput,
+    Xdotool,
+    Ydotool,
+}
+
+impl Typer {
+    /// Picks a backend for this session, if there is one.
+    #[cfg(target_os = "linux")]
+    pub fn detect() -> Option<Typer> {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            Some(Typer::Uinput)
+        } else if env::var_os("DISPLAY").is_some() {
+            Some(Typer::XTest)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn detect() -> Option<Typer> {
+        None
+    }
+
+    /// Parses a backend name, where `auto` detects one.
+    pub fn from_name(name: &str) -> Option<Option<Typer>> {
+        match name {
+            #[cfg(target_os = "linux")]
+            "xtest" => Some(Some(Typer::XTest)),
+            #[cfg(target_os = "linux")]
+            "uinput" => Some(Some(Typer::Uinput)),
+            "xdotool" => Some(Some(Typer::Xdotool)),
+            "ydotool" => Some(Some(Typer::Ydotool)),
+            "auto" => Some(Typer::detect()),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(target_os = "linux")]
+            Typer::XTest => "xtest",
+            #[cfg(target_os = "linux")]
+            Typer::Uinput => "uinput",
+            Typer::Xdotool => "xdotool",
+            Typer::Ydotool => "ydotool",
+        }
+    }
+
+    /// Types the text into whichever window has the focus.
+    pub fn type_text(&self, text: &str) -> Result<(), DerowinError> {
+        debug!("Typing {} with {}", redact(text), self.name());
+        let result = match *self {
+            #[cfg(target_os = "linux")]
+            Typer::XTest => xtest::type_text(text),
+            #[cfg(target_os = "linux")]
+            Typer::Uinput => uinput::type_text(text),
+            Typer::Xdotool => run("xdotool", &["type", "--clearmodifiers", "--"], text),
+            Typer::Ydotool => run("ydotool", &["type", "--"], text),
+        };
+        result.map_err(|reason| DerowinError::Typing(self.name(), reason))
+    }
+}
+
+fn run(program: &str, args: &[&str], text: &str) -> Result<(), String> {
+    Command::new(program)
+        .args(args)
+        .arg(text)
+        .status()
+        .and_then(clipboard::check_status)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(target_os = "linux")]
+mod xtest {
+    use std::os::raw::{c_int, c_ulong};
+    use std::ptr;
+    use std::thread;
+    use std::time::Duration;
+
+    use x11::{keysym, xlib, xtest};
+
+    /// Time for clients to pick up a keyboard mapping change.
+    const REMAP_DELAY: u64 = 10;
+
+    pub fn type_text(text: &str) -> Result<(), String> {
+        unsafe {
+            let display = xlib::XOpenDisplay(ptr::null());
+            if display.is_null() {
+                return Err(String::from("could not open the X display"));
+            }
+            let result = type_with_spare_keycode(display, text);
+            xlib::XCloseDisplay(display);
+            result
+        }
+    }
+
+    /// Types each character by mapping it to an unused keycode and
+    /// pressing that, which works whatever the keyboard layout is.
+    unsafe fn type_with_spare_keycode(display: *mut xlib::Display, text: &str)
+        -> Result<(), String>
+    {
+        let (mut min, mut max) = (0, 0);
+        xlib::XDisplayKeycodes(display, &mut min, &mut max);
+        let mut per_keycode = 0;
+        let map = xlib::XGetKeyboardMapping(display, min as u8, max - min + 1, &mut per_keycode);
+        if map.is_null() {
+            return Err(String::from("could not read the keyboard mapping"));
+        }
+        let spare = (min..max + 1).rev().find(|&keycode| {
+            let start = ((keycode - min) * per_keycode) as isize;
+            (0..per_keycode as isize).all(|i| *map.offset(start + i) == 0)
+        });
+        xlib::XFree(map as *mut _);
+        let spare = spare.ok_or_else(|| String::from("no free keycode to type with"))?;
+        for ch in text.chars() {
+            remap(display, spare, keysym_for(ch));
+            thread::sleep(Duration::from_millis(REMAP_DELAY));
+            xtest::XTestFakeKeyEvent(display, spare as u32, xlib::True, 0);
+            xtest::XTestFakeKeyEvent(display, spare as u32, xlib::False, 0);
+            xlib::XSync(display, xlib::False);
+        }
+        // Gives the keycode back, after the last press has been seen.
+        thread::sleep(Duration::from_millis(REMAP_DELAY));
+        remap(display, spare, 0);
+        Ok(())
+    }
+
+    unsafe fn remap(display: *mut xlib::Display, keycode: c_int, keysym: c_ulong) {
+        let mut keysyms = [keysym, keysym];
+        xlib::XChangeKeyboardMapping(display, keycode, 2, keysyms.as_mut_ptr(), 1);
+        xlib::XSync(display, xlib::False);
+    }
+
+    fn keysym_for(ch: char) -> c_ulong {
+        match ch {
+            '\n' => keysym::XK_Return as c_ulong,
+            '\t' => keysym::XK_Tab as c_ulong,
+            // Latin-1 keysyms are the code points themselves.
+            ' '..='~' | '\u{a0}'..='\u{ff}' => ch as c_ulong,
+            _ => 0x0100_0000 + ch as c_ulong,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod uinput {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Write};
+    use std::mem;
+    use std::os::raw::c_ulong;
+    use std::os::unix::io::AsRawFd;
+    use std::slice;
+    use std::thread;
+    use std::time::Duration;
+
+    use libc;
+
+    const UI_SET_EVBIT: c_ulong = 0x4004_5564;
+    const UI_SET_KEYBIT: c_ulong = 0x4004_5565;
+    const UI_DEV_CREATE: c_ulong = 0x5501;
+    const UI_DEV_DESTROY: c_ulong = 0x5502;
+    const EV_SYN: u16 = 0x00;
+    const EV_KEY: u16 = 0x01;
+    const SYN_REPORT: u16 = 0;
+    const BUS_VIRTUAL: u16 = 0x06;
+
+    const KEY_LEFTCTRL: u16 = 29;
+    const KEY_LEFTSHIFT: u16 = 42;
+    const KEY_ENTER: u16 = 28;
+    const KEY_SPACE: u16 = 57;
+    const KEY_U: u16 = 22;
+    /// The keys for hex digits 0 to f, on a US layout.
+    const HEX_KEYS: [u16; 16] = [11, 2, 3, 4, 5, 6, 7, 8, 9, 10, 30, 48, 46, 32, 18, 33];
+
+    /// Time for the desktop to notice the new device.
+    const SETTLE_DELAY: u64 = 200;
+    const KEY_DELAY: u64 = 2;
+
+    #[repr(C)]
+    struct InputId {
+        bustype: u16,
+        vendor: u16,
+        product: u16,
+        version: u16,
+    }
+
+    #[repr(C)]
+    struct UinputUserDev {
+        name: [u8; 80],
+        id: InputId,
+        ff_effects_max: u32,
+        absmax: [i32; 64],
+        absmin: [i32; 64],
+        absfuzz: [i32; 64],
+        absflat: [i32; 64],
+    }
+
+    #[repr(C)]
+    struct InputEvent {
+        time: libc::timeval,
+        kind: u16,
+        code: u16,
+        value: i32,
+    }
+
+    struct Device {
+        file: File,
+    }
+
+    impl Device {
+        fn create() -> io::Result<Device> {
+            let file = OpenOptions::new().write(true).open("/dev/uinput")?;
+            let device = Device { file: file };
+            device.ioctl(UI_SET_EVBIT, EV_KEY)?;
+            let keys = [KEY_LEFTCTRL, KEY_LEFTSHIFT, KEY_ENTER, KEY_SPACE, KEY_U];
+            for &key in keys.iter().chain(HEX_KEYS.iter()) {
+                device.ioctl(UI_SET_KEYBIT, key)?;
+            }
+            let mut setup: UinputUserDev = unsafe { mem::zeroed() };
+            let name = b"derowin";
+            setup.name[..name.len()].copy_from_slice(name);
+            setup.id.bustype = BUS_VIRTUAL;
+            (&device.file).write_all(unsafe { as_bytes(&setup) })?;
+            device.ioctl(UI_DEV_CREATE, 0)?;
+            thread::sleep(Duration::from_millis(SETTLE_DELAY));
+            Ok(device)
+        }
+
+        fn ioctl(&self, request: c_ulong, arg: u16) -> io::Result<()> {
+            let result = unsafe {
+                libc::ioctl(self.file.as_raw_fd(), request as _, arg as libc::c_int)
+            };
+            if result < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn emit(&self, kind: u16, code: u16, value: i32) -> io::Result<()> {
+            let event = InputEvent {
+                time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+                kind: kind,
+                code: code,
+                value: value,
+            };
+            (&self.file).write_all(unsafe { as_bytes(&event) })
+        }
+
+        /// Presses and releases the keys, holding each until the end.
+        fn chord(&self, keys: &[u16]) -> io::Result<()> {
+            for &key in keys {
+                self.emit(EV_KEY, key, 1)?;
+                self.emit(EV_SYN, SYN_REPORT, 0)?;
+            }
+            for &key in keys.iter().rev() {
+                self.emit(EV_KEY, key, 0)?;
+                self.emit(EV_SYN, SYN_REPORT, 0)?;
+            }
+            thread::sleep(Duration::from_millis(KEY_DELAY));
+            Ok(())
+        }
+    }
+
+    impl Drop for Device {
+        fn drop(&mut self) {
+            if let Err(e) = self.ioctl(UI_DEV_DESTROY, 0) {
+                warn!("Could not remove the virtual keyboard: {}", e);
+            }
+        }
+    }
+
+    unsafe fn as_bytes<T>(value: &T) -> &[u8] {
+        slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+    }
+
+    pub fn type_text(text: &str) -> Result<(), String> {
+        type_with_device(text).map_err(|e| e.to_string())
+    }
+
+    fn type_with_device(text: &str) -> io::Result<()> {
+        let device = Device::create()?;
+        for ch in text.chars() {
+            if ch == '\n' {
+                device.chord(&[KEY_ENTER])?;
+                continue;
+            }
+            device.chord(&[KEY_LEFTCTRL, KEY_LEFTSHIFT, KEY_U])?;
+            for digit in format!("{:x}", ch as u32).chars() {
+                let digit = digit.to_digit(16).unwrap() as usize;
+                device.chord(&[HEX_KEYS[digit]])?;
+            }
+            device.chord(&[KEY_SPACE])?;
+        }
+        // The last keys are still being read when the device goes away.
+        thread::sleep(Duration::from_millis(SETTLE_DELAY));
+        Ok(())
+    }
+}
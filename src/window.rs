@@ -0,0 +1,148 @@
+//! Window behavior options, and remembering where the window was.
+//!
+//! The options are read from these config keys, which the matching
+//! command line flags override:
+//!
+//! - `window.always-on-top` (`--on-top`)
+//! - `window.borderless` (`--borderless`)
+//! - `window.opacity`, from 0.0 to 1.0 (`--opacity`)
+//! - `window.remember-geometry`, on by default (`--remember-geometry`,
+//!   `--no-remember-geometry`)
+//! - `window.layout`, `normal` or `palette` (`--palette`)
+//! - `window.split`, `off`, `below` or `side` (`--split`), to show the
+//!   romanized source above or beside the converted text
+//! - `window.annotations` (`--annotations`), to show the converted text
+//!   with what each word was typed as over it
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use config::{self, Config};
+use error::DerowinError;
+use session;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// A resizable window with a status bar.
+    Normal,
+    /// A compact window without a status bar, that sizes itself to the
+    /// text.
+    Palette,
+}
+
+/// Where the source pane goes in the normal layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    Off,
+    /// The source above, the output below.
+    Below,
+    /// The source on the left, the output on the right.
+    Side,
+}
+
+impl Split {
+    pub fn from_name(name: &str) -> Option<Split> {
+        match name {
+            "off" => Some(Split::Off),
+            "below" => Some(Split::Below),
+            "side" => Some(Split::Side),
+            _ => None,
+        }
+    }
+
+    /// The split after this one, for the key that goes through them.
+    pub fn next(&self) -> Split {
+        match *self {
+            Split::Off => Split::Below,
+            Split::Below => Split::Side,
+            Split::Side => Split::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+    pub always_on_top: bool,
+    pub borderless: bool,
+    pub opacity: Option<f32>,
+    pub remember_geometry: bool,
+    pub layout: Layout,
+    pub split: Split,
+    pub annotations: bool,
+}
+
+impl WindowOptions {
+    pub fn from_config(config: &Config) -> Result<WindowOptions, DerowinError> {
+        let opacity = config.parse_with("window.opacity", |value| {
+            value.parse::<f32>().ok().filter(|&o| o >= 0.0 && o <= 1.0)
+        })?;
+        let layout = config.parse_with("window.layout", |value| match value {
+            "normal" => Some(Layout::Normal),
+            "palette" => Some(Layout::Palette),
+            _ => None,
+        })?;
+        Ok(WindowOptions {
+            always_on_top: config.parse_with("window.always-on-top", config::parse_bool)?
+                .unwrap_or(false),
+            borderless: config.parse_with("window.borderless", config::parse_bool)?
+                .unwrap_or(false),
+            opacity: opacity,
+            remember_geometry: config.parse_with("window.remember-geometry", config::parse_bool)?
+                .unwrap_or(true),
+            layout: layout.unwrap_or(Layout::Normal),
+            split: config.parse_with("window.split", Split::from_name)?.unwrap_or(Split::Off),
+            annotations: config.parse_with("window.annotations", config::parse_bool)?
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// The position and size of the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Geometry {
+    /// Reads the geometry saved by the last run.
+    pub fn load() -> Option<Geometry> {
+        let path = geometry_path()?;
+        let mut contents = String::new();
+        if File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+            return None;
+        }
+        let numbers = contents.split_whitespace()
+            .map(|part| part.parse::<i32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        match numbers.as_slice() {
+            &[x, y, width, height] if width > 0 && height > 0 => {
+                debug!("Restoring window geometry {:?}", (x, y, width, height));
+                Some(Geometry { x: x, y: y, width: width, height: height })
+            }
+            _ => {
+                warn!("Ignoring malformed window geometry in {}", path.display());
+                None
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match geometry_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let contents = format!("{} {} {} {}\n", self.x, self.y, self.width, self.height);
+        match session::write_atomically(&path, &contents) {
+            Ok(()) => debug!("Saved window geometry to {}", path.display()),
+            Err(e) => warn!("Could not save window geometry to {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn geometry_path() -> Option<PathBuf> {
+    session::state_dir().map(|dir| dir.join("window"))
+}